@@ -0,0 +1,181 @@
+use crate::{Engine, Free, Issue, ProcessCache, SideEffect};
+use std::collections::{BTreeSet, HashMap};
+use std::mem::take;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A single template to be rendered by [`Engine::process_batch`](struct.Engine.html#method.process_batch)
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct RenderJob {
+    /// The template
+    pub template: String,
+    /// Variables that are added to (or override) the engine's variables for this template only
+    pub vars: HashMap<String, String>,
+    /// The root path for this template, overriding the engine's root path if set
+    pub root_path: Option<PathBuf>,
+}
+
+impl RenderJob {
+    /// Creates a job for a template without any variable overlays
+    #[inline]
+    pub fn new(template: String) -> Self {
+        Self {
+            template,
+            ..Self::default()
+        }
+    }
+
+    /// Adds variables to the overlay of this job
+    #[inline]
+    pub fn with_vars(mut self, vars: HashMap<String, String>) -> Self {
+        self.vars.extend(vars);
+        self
+    }
+
+    /// Sets the root path for this job
+    #[inline]
+    pub fn with_root_path(mut self, path: PathBuf) -> Self {
+        self.root_path = Some(path);
+        self
+    }
+}
+
+/// The result of a job rendered by [`Engine::process_job_tracked`](struct.Engine.html#method.process_job_tracked)
+#[derive(Debug)]
+pub(crate) struct TrackedJob {
+    pub output: String,
+    pub issues: Vec<Issue>,
+    /// See [`Engine::accessed_files`](struct.Engine.html#method.accessed_files)
+    pub accessed_files: BTreeSet<PathBuf>,
+    /// See [`Engine::audit_log`](struct.Engine.html#method.audit_log)
+    pub audit_log: Vec<SideEffect>,
+}
+
+impl Engine<Free> {
+    /// A copy of `self` for rendering `job`, which looks up processes in `shared`
+    /// and caches the processes it runs itself separately
+    #[inline]
+    fn job_engine(&self, job: &mut RenderJob, shared: &Option<Arc<ProcessCache>>) -> Self {
+        let cache = shared.as_ref().map(|c| c.empty_like());
+        let mut en = self.clone_with_process_cache(cache);
+        en.shared_process_cache = shared.clone();
+        en.vars.extend(take(&mut job.vars));
+        if job.root_path.is_some() {
+            en.root_path = job.root_path.take();
+        }
+        en
+    }
+
+    /// Like [`process_new`](#method.process_new), but also returns the files that were accessed
+    /// and the side effects that were recorded
+    #[inline]
+    pub(crate) fn process_job_tracked(&mut self, template: String) -> TrackedJob {
+        self.accessed_files.clear();
+        self.audit_log.clear();
+        let (output, issues) = self.process_new(template);
+        TrackedJob {
+            output,
+            issues,
+            accessed_files: take(&mut self.accessed_files),
+            audit_log: take(&mut self.audit_log),
+        }
+    }
+
+    /// Renders many templates in parallel, using up to `threads` threads
+    ///
+    /// Every job is rendered by its own copy of `self` (sharing the registered commands),
+    /// so changes one template makes to the engine state are not visible to the others.\
+    /// The jobs look up processes in the [process cache](#method.with_process_cache) of `self`,
+    /// but the processes they run are not added to it (use [`process_batch_caching`](#method.process_batch_caching) for that).
+    /// The results are in the same order as `jobs`, regardless of which thread rendered what.
+    ///
+    /// If `threads` is `0`, the available parallelism of the system is used
+    #[inline]
+    pub fn process_batch(&self, jobs: Vec<RenderJob>, threads: usize) -> Vec<(String, Vec<Issue>)> {
+        self.process_batch_with(jobs, threads, Self::process_new).0
+    }
+
+    /// Like [`process_batch`](#method.process_batch), but also returns the processes the jobs ran
+    ///
+    /// The returned cache only contains the new entries (with the maximum age of `self`'s cache),
+    /// so adding them to the cache of `self` with [`ProcessCache::merge`](struct.ProcessCache.html#method.merge)
+    /// is cheap. It is `None` if `self` has no process cache
+    pub fn process_batch_caching(
+        &self,
+        jobs: Vec<RenderJob>,
+        threads: usize,
+    ) -> (Vec<(String, Vec<Issue>)>, Option<ProcessCache>) {
+        self.process_batch_with(jobs, threads, Self::process_new)
+    }
+
+    /// The implementation of [`process_batch_caching`](#method.process_batch_caching), with a custom function to render a job
+    pub(crate) fn process_batch_with<R: Send>(
+        &self,
+        jobs: Vec<RenderJob>,
+        threads: usize,
+        render: fn(&mut Self, String) -> R,
+    ) -> (Vec<R>, Option<ProcessCache>) {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(jobs.len());
+        // the jobs only read the cache, so it is copied once instead of once per job
+        let shared = self.process_cache.clone().map(Arc::new);
+        let render_job = |mut job: RenderJob| {
+            let mut en = self.job_engine(&mut job, &shared);
+            let res = render(&mut en, job.template);
+            (res, en.process_cache)
+        };
+        let results = if threads <= 1 {
+            jobs.into_iter().map(render_job).collect::<Vec<_>>()
+        } else {
+            render_parallel(jobs, threads, &render_job)
+        };
+
+        let mut added = shared.as_ref().map(|c| c.empty_like());
+        let results = results
+            .into_iter()
+            .map(|(res, cache)| {
+                if let (Some(added), Some(cache)) = (added.as_mut(), cache) {
+                    added.merge(cache);
+                }
+                res
+            })
+            .collect();
+        (results, added)
+    }
+}
+
+/// Calls `render` for every job, on up to `threads` threads
+fn render_parallel<R: Send>(
+    jobs: Vec<RenderJob>,
+    threads: usize,
+    render: &(impl Fn(RenderJob) -> R + Sync),
+) -> Vec<R> {
+    let jobs = jobs
+        .into_iter()
+        .map(|j| Mutex::new(Some(j)))
+        .collect::<Vec<_>>();
+    let results = jobs.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let job = match jobs.get(i) {
+                    Some(job) => job.lock().unwrap().take().unwrap(),
+                    None => break,
+                };
+                *results[i].lock().unwrap() = Some(render(job));
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|r| r.into_inner().unwrap().unwrap())
+        .collect()
+}
@@ -0,0 +1,404 @@
+//! A language server for ppm templates, speaking the Language Server Protocol over stdio
+//!
+//! It supports
+//! - diagnostics, found by [`Engine::analyze`](../ppm/struct.Engine.html#method.analyze), which has no side effects
+//! - completion of command names and variables after `(%` and in `var`, `raw` and `safe`
+//! - hover documentation for the predefined commands and for variables
+//! - go-to-definition for variables bound by `let`, `for`, `sort_by` and `re_captures`
+//! - folding ranges for commands and comments that span multiple lines
+
+use ppm::predefined_commands::{command_info, get_handlers};
+use ppm::{CommandSet, Engine, Free, Severity, Span, SyntaxNode, SyntaxTree};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+use text::LineIndex;
+
+mod text;
+
+/// Reads one message, returning `None` at the end of input
+fn read_message(r: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    serde_json::from_slice(&buf)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(w: &mut impl Write, msg: &Value) -> std::io::Result<()> {
+    let s = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", s.len(), s)?;
+    w.flush()
+}
+
+/// An open document
+struct Document {
+    text: String,
+    lines: LineIndex,
+    tree: SyntaxTree,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        Self {
+            lines: LineIndex::new(&text),
+            tree: SyntaxTree::parse(&text),
+            text,
+        }
+    }
+}
+
+/// Where a completion happens
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum CompletionKind {
+    /// Right after `(%`, where both commands and variables (as `(%name%)`) fit
+    CommandOrVariable,
+    /// In the argument of `var`, `raw` or `safe`
+    Variable,
+}
+
+struct Server {
+    engine: Engine<Free>,
+    /// The names of the engine's commands, sorted
+    command_names: Vec<String>,
+    docs: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn capabilities() -> Value {
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "completionProvider": { "triggerCharacters": ["%", " "] },
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "foldingRangeProvider": true,
+            },
+            "serverInfo": { "name": "ppm-lsp", "version": env!("CARGO_PKG_VERSION") },
+        })
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let diagnostics = match self.docs.get(uri) {
+            Some(doc) => self
+                .engine
+                .analyze(&doc.text)
+                .into_iter()
+                .map(|issue| {
+                    let severity = match issue.severity() {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    };
+                    json!({
+                        "range": doc.lines.range(&doc.text, issue.span.start, issue.span.end()),
+                        "severity": severity,
+                        "code": issue.id,
+                        "source": "ppm",
+                        "message": issue.msg,
+                    })
+                })
+                .collect(),
+            None => vec![],
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    fn is_command(&self, name: &str) -> bool {
+        !name.is_empty()
+            && self
+                .command_names
+                .binary_search_by(|n| n[..].cmp(name))
+                .is_ok()
+    }
+
+    /// The variables known in a document: the engine's and the ones bound in it
+    fn variables(&self, doc: &Document) -> BTreeSet<String> {
+        let mut res = self.engine.vars.keys().cloned().collect::<BTreeSet<_>>();
+        res.extend(doc.tree.bindings(&doc.text).into_iter().map(|b| b.name));
+        res
+    }
+
+    /// The name and region of the variable referenced at `offset`, if any
+    fn variable_at<'a>(&self, doc: &'a Document, offset: usize) -> Option<(&'a str, Span)> {
+        let cmd = doc.tree.command_at(offset)?;
+        let contains = |start: usize, len: usize| start <= offset && offset <= start + len;
+        if contains(cmd.name_span.start, cmd.name_span.len)
+            && cmd.body_span.len == 0
+            && !self.is_command(&cmd.name)
+        {
+            return Some((&cmd.name, cmd.name_span));
+        }
+        if ["var", "raw", "safe"].contains(&&cmd.name[..])
+            && contains(cmd.body_span.start, cmd.body_span.len)
+        {
+            let body = cmd.body_span.index_str(&doc.text);
+            let start = cmd.body_span.start + body.len() - body.trim_start().len();
+            return Some((body.trim(), Span::new(start, body.trim().len())));
+        }
+        None
+    }
+
+    fn completion_kind(text: &str, offset: usize) -> Option<(CompletionKind, usize)> {
+        let before = &text[..offset];
+        let word_start = before
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| Engine::is_invalid_command_char(c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let prefix = &before[..word_start];
+        let after_open = prefix.ends_with("(%")
+            || (prefix.ends_with(char::is_whitespace) && prefix.trim_end().ends_with("(%-"));
+        if after_open {
+            return Some((CompletionKind::CommandOrVariable, word_start));
+        }
+        let in_var = ["(%var ", "(%raw ", "(%safe "]
+            .iter()
+            .any(|p| prefix.ends_with(p));
+        if in_var {
+            return Some((CompletionKind::Variable, word_start));
+        }
+        None
+    }
+
+    fn completion(&self, doc: &Document, offset: usize) -> Value {
+        let (kind, word_start) = match Self::completion_kind(&doc.text, offset) {
+            Some(x) => x,
+            None => return Value::Null,
+        };
+        let mut items = vec![];
+        if kind == CompletionKind::CommandOrVariable {
+            for name in &self.command_names {
+                if name.is_empty() {
+                    continue;
+                }
+                let mut item = json!({ "label": name, "kind": 3 });
+                if let Some(info) = command_info(name) {
+                    item["detail"] = json!(info.usage);
+                    item["documentation"] = json!(info.summary);
+                }
+                items.push(item);
+            }
+        }
+        for var in self.variables(doc) {
+            items.push(json!({ "label": var, "kind": 6 }));
+        }
+        let range = doc.lines.range(&doc.text, word_start, offset);
+        for item in &mut items {
+            item["textEdit"] = json!({ "range": range, "newText": item["label"] });
+        }
+        json!(items)
+    }
+
+    fn hover(&self, doc: &Document, offset: usize) -> Value {
+        let cmd = match doc.tree.command_at(offset) {
+            Some(cmd) => cmd,
+            None => return Value::Null,
+        };
+        let (contents, span) = if let Some((var, span)) = self.variable_at(doc, offset) {
+            let mut s = format!("variable `{}`", var);
+            if let Some(value) = self.engine.vars.get(var) {
+                s += &format!("\n\n= `{:?}`", value);
+            }
+            let bindings = doc.tree.bindings(&doc.text);
+            if let Some(b) = bindings.iter().find(|b| b.name == var) {
+                let line = doc.lines.line(b.span.start) + 1;
+                s += &format!("\n\nbound in line {}", line);
+            }
+            (s, span)
+        } else if cmd.name_span.start <= offset && offset <= cmd.name_span.end() {
+            match command_info(&cmd.name).filter(|_| self.is_command(&cmd.name)) {
+                Some(info) => (
+                    format!("```\n{}\n```\n{}", info.usage, info.summary),
+                    cmd.name_span,
+                ),
+                None if self.is_command(&cmd.name) => {
+                    (format!("command `{}`", cmd.name), cmd.name_span)
+                }
+                None => return Value::Null,
+            }
+        } else {
+            return Value::Null;
+        };
+        json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": doc.lines.range(&doc.text, span.start, span.end()),
+        })
+    }
+
+    fn definition(&self, uri: &str, doc: &Document, offset: usize) -> Value {
+        let var = match self.variable_at(doc, offset) {
+            Some((var, _)) => var,
+            None => return Value::Null,
+        };
+        let bindings = doc.tree.bindings(&doc.text);
+        let mut candidates = bindings.iter().filter(|b| b.name == var);
+        // the closest binding before the use, or else the first one
+        let binding = candidates
+            .clone()
+            .rev()
+            .find(|b| b.span.start < offset)
+            .or_else(|| candidates.next());
+        match binding {
+            Some(b) => json!({
+                "uri": uri,
+                "range": doc.lines.range(&doc.text, b.span.start, b.span.end()),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn folding_ranges(&self, doc: &Document) -> Value {
+        fn visit(doc: &Document, nodes: &[SyntaxNode], res: &mut Vec<Value>) {
+            for node in nodes {
+                let span = node.span();
+                let start = doc.lines.line(span.start);
+                let end = doc.lines.line(span.end().saturating_sub(1).max(span.start));
+                if end > start {
+                    let mut range = json!({ "startLine": start, "endLine": end });
+                    if let SyntaxNode::Comment(_) = node {
+                        range["kind"] = json!("comment");
+                    }
+                    res.push(range);
+                }
+                if let SyntaxNode::Command(cmd) = node {
+                    visit(doc, &cmd.children, res);
+                }
+            }
+        }
+        let mut res = vec![];
+        visit(doc, &doc.tree.nodes, &mut res);
+        json!(res)
+    }
+
+    /// Handles a request, returning its result
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if method == "initialize" {
+            return Ok(Self::capabilities());
+        }
+        if method == "shutdown" {
+            self.shutdown = true;
+            return Ok(Value::Null);
+        }
+
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let doc = match self.docs.get(uri) {
+            Some(doc) => doc,
+            None if method.starts_with("textDocument/") => return Ok(Value::Null),
+            None => return Err((-32601, format!("unsupported method `{}`", method))),
+        };
+        let offset = doc.lines.offset(&doc.text, &params["position"]);
+        Ok(match method {
+            "textDocument/completion" => self.completion(doc, offset),
+            "textDocument/hover" => self.hover(doc, offset),
+            "textDocument/definition" => self.definition(uri, doc, offset),
+            "textDocument/foldingRange" => self.folding_ranges(doc),
+            _ => return Err((-32601, format!("unsupported method `{}`", method))),
+        })
+    }
+
+    /// Handles a notification, returning the notifications to send in response
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.docs
+                    .insert(uri.clone(), Document::new(text.to_string()));
+            }
+            "textDocument/didChange" => {
+                // the server only supports full synchronisation, so the last change is the whole text
+                let changes = params["contentChanges"].as_array();
+                match changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    Some(text) => {
+                        self.docs
+                            .insert(uri.clone(), Document::new(text.to_string()));
+                    }
+                    None => return vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+            }
+            "exit" => std::process::exit(if self.shutdown { 0 } else { 1 }),
+            _ => return vec![],
+        }
+        vec![self.diagnostics(&uri)]
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    // the same commands as `ppm` enables by default
+    let mut engine = Engine::new(HashMap::new());
+    engine
+        .add_commands(get_handlers(&CommandSet::ALL))
+        .expect("internal error: default command names invalid");
+    let command_names = engine
+        .command_names()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let mut server = Server {
+        engine,
+        command_names,
+        docs: HashMap::new(),
+        shutdown: false,
+    };
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut output = stdout.lock();
+
+    while let Some(msg) = read_message(&mut input)? {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        match msg.get("id") {
+            Some(id) if !method.is_empty() => {
+                let response = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                write_message(&mut output, &response)?;
+            }
+            // responses to requests of the server, which it doesn't send
+            Some(_) => (),
+            None => {
+                for msg in server.notification(method, params) {
+                    write_message(&mut output, &msg)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Conversion between byte offsets and LSP positions (lines and UTF-16 columns)
+
+use serde_json::{json, Value};
+
+/// The start offsets of all lines of a document
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { starts }
+    }
+
+    /// The LSP position of a byte offset, which is clamped to the text
+    pub fn position(&self, text: &str, offset: usize) -> Value {
+        let mut offset = offset.min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.starts.partition_point(|&s| s <= offset) - 1;
+        let col = text[self.starts[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum::<usize>();
+        json!({ "line": line, "character": col })
+    }
+
+    /// The LSP range of a byte range
+    pub fn range(&self, text: &str, start: usize, end: usize) -> Value {
+        json!({ "start": self.position(text, start), "end": self.position(text, end) })
+    }
+
+    /// The byte offset of an LSP position, clamped to the line (or the text)
+    pub fn offset(&self, text: &str, pos: &Value) -> usize {
+        let line = pos["line"].as_u64().unwrap_or(0) as usize;
+        let col = pos["character"].as_u64().unwrap_or(0) as usize;
+        let start = match self.starts.get(line) {
+            Some(&s) => s,
+            None => return text.len(),
+        };
+        let end = self.starts.get(line + 1).map_or(text.len(), |&e| e - 1);
+        let mut utf16 = 0;
+        for (i, c) in text[start..end].char_indices() {
+            if utf16 >= col {
+                return start + i;
+            }
+            utf16 += c.len_utf16();
+        }
+        end
+    }
+
+    /// The line a byte offset is in
+    pub fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&s| s <= offset) - 1
+    }
+}
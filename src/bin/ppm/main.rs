@@ -0,0 +1,581 @@
+//! The `ppm` command-line tool
+//!
+//! Run `ppm --help` for usage information
+
+use ppm::predefined_commands::get_handlers;
+use ppm::{
+    format_template, CommandSet, DataFormat, DryRun, Engine, Escaper, FileWatcher, FormatOptions,
+    Free, Issue, RowCol, Severity, SideEffect, TreeOptions, TreeReport, TreeWatcher,
+};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{Duration, SystemTime};
+
+mod repl;
+
+const USAGE: &str = "\
+Usage: ppm [OPTIONS] [INPUT]
+       ppm [OPTIONS] --repl
+       ppm --format [--indent N] [--check] [-o PATH] [INPUT]
+       ppm --lint [OPTIONS] [INPUT]
+
+Renders the template INPUT (or stdin if it is missing or `-`)
+
+If INPUT is a directory, all templates in it are rendered into the directory given by --output,
+mirroring its structure, and all other files are copied there.
+Each template uses its own directory as root path
+
+With --repl, lines read from stdin are processed one after another by the same engine
+(type `:help` there for more information)
+
+With --format, the template is formatted instead of rendered: the spacing and escapes inside
+commands are normalized and, with --indent, lines removed by trim markers are indented by
+nesting level.
+What the template renders to stays the same
+
+With --dry-run, processes are not run and no file is read or written (except the templates and
+--vars files). What would have been done is printed to stderr instead, and the output goes to
+stdout (in directory mode, it is discarded)
+
+With --lint, the template is checked for likely mistakes without rendering it, so no command
+(like `run`) is executed
+
+Options:
+  -o, --output PATH          write the output to PATH instead of stdout
+                             (in directory mode, PATH is the output directory)
+  -D NAME=VALUE              set a variable (can be repeated)
+      --vars FILE            load variables from a data file (JSON, TOML, INI, .env or CSV,
+                             guessed from the extension) (can be repeated)
+      --root DIR             (file mode) resolve relative paths against DIR
+                             (default: the directory of INPUT, or the current directory)
+      --ext EXT              (directory mode) files ending with `.EXT` are templates, the extension
+                             is removed from the output name (can be repeated, default: `ppm`)
+      --glob PATTERN         (directory mode) files matching PATTERN are templates (can be repeated)
+      --no-copy              (directory mode) don't copy files that are not templates
+  -j, --jobs N               (directory mode) render with N threads (default: one per CPU)
+      --diagnostics FORMAT   how to print issues: `human` (default), `short` or `json`
+      --commands SETS        comma-separated command sets to enable (default: all of them)
+                             available: core, escape, fs, process, system, regex
+      --fail-on SEVERITY     exit with status 1 if there are issues of at least this severity:
+                             `error` (default), `warning` or `never`
+  -i, --repl                 start an interactive session instead of rendering INPUT
+  -w, --watch                keep running and render again whenever a template, an included file
+                             or a data file changes (in directory mode, only affected files are
+                             rendered again)
+      --interval MS          how often to check for changes when watching (default: 500)
+      --dry-run              record side effects instead of performing them (see above)
+      --placeholder CMD=TEXT (dry run) output TEXT for the command CMD (`run`, `include` or
+                             `lsdir`) instead of nothing (can be repeated)
+      --format               print the formatted template instead of rendering it
+      --indent N             (format mode) indent nested lines by N spaces, or a tab if N is `tab`
+      --lint                 report likely mistakes in the template instead of rendering it
+      --check                (format mode) don't print anything, exit with status 1 if the
+                             template is not formatted
+  -h, --help                 print this help
+
+Exit status: 0 on success, 1 if there were issues (see --fail-on), 2 on invalid usage or IO errors";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum DiagnosticsFormat {
+    Human,
+    Short,
+    Json,
+}
+
+#[derive(Debug)]
+struct Options {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    defines: Vec<(String, String)>,
+    var_files: Vec<PathBuf>,
+    root: Option<PathBuf>,
+    diagnostics: DiagnosticsFormat,
+    commands: Vec<CommandSet>,
+    fail_on: Option<Severity>,
+    tree: TreeOptions,
+    watch: bool,
+    interval: Duration,
+    repl: bool,
+    dry_run: Option<DryRun>,
+    format: bool,
+    lint: bool,
+    check: bool,
+    indent: Option<String>,
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("ppm: {}\n\n{}", msg, USAGE);
+    exit(2)
+}
+
+fn fatal(msg: &str) -> ! {
+    eprintln!("ppm: {}", msg);
+    exit(2)
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut res = Self {
+            input: None,
+            output: None,
+            defines: vec![],
+            var_files: vec![],
+            root: None,
+            diagnostics: DiagnosticsFormat::Human,
+            commands: CommandSet::ALL.to_vec(),
+            fail_on: Some(Severity::Error),
+            tree: TreeOptions::new(),
+            watch: false,
+            interval: Duration::from_millis(500),
+            repl: false,
+            dry_run: None,
+            format: false,
+            lint: false,
+            check: false,
+            indent: None,
+        };
+        while let Some(arg) = args.next() {
+            // support `--opt=value` in addition to `--opt value`
+            let (arg, inline_val) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => {
+                    (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = |name: &str| {
+                inline_val
+                    .clone()
+                    .or_else(|| args.next())
+                    .unwrap_or_else(|| usage_error(&format!("missing value for {}", name)))
+            };
+            match &arg[..] {
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    exit(0)
+                }
+                "-o" | "--output" => res.output = Some(PathBuf::from(value(&arg))),
+                "-D" => {
+                    let def = value(&arg);
+                    match def.find('=') {
+                        Some(i) => res
+                            .defines
+                            .push((def[..i].to_string(), def[i + 1..].to_string())),
+                        None => usage_error(&format!("expected NAME=VALUE, got `{}`", def)),
+                    }
+                }
+                s if s.starts_with("-D") => {
+                    let def = &s[2..];
+                    match def.find('=') {
+                        Some(i) => res
+                            .defines
+                            .push((def[..i].to_string(), def[i + 1..].to_string())),
+                        None => usage_error(&format!("expected NAME=VALUE, got `{}`", def)),
+                    }
+                }
+                "--vars" => res.var_files.push(PathBuf::from(value(&arg))),
+                "--root" => res.root = Some(PathBuf::from(value(&arg))),
+                "--diagnostics" => {
+                    res.diagnostics = match &value(&arg)[..] {
+                        "human" => DiagnosticsFormat::Human,
+                        "short" => DiagnosticsFormat::Short,
+                        "json" => DiagnosticsFormat::Json,
+                        s => usage_error(&format!("unknown diagnostics format `{}`", s)),
+                    }
+                }
+                "--commands" => {
+                    res.commands = value(&arg)
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(|s| {
+                            CommandSet::from_name(s).unwrap_or_else(|| {
+                                usage_error(&format!("unknown command set `{}`", s))
+                            })
+                        })
+                        .collect();
+                }
+                "--fail-on" => {
+                    res.fail_on = match &value(&arg)[..] {
+                        "error" => Some(Severity::Error),
+                        "warning" => Some(Severity::Warning),
+                        "never" => None,
+                        s => usage_error(&format!("unknown severity `{}`", s)),
+                    }
+                }
+                "--ext" => res.tree = res.tree.clone().with_extension(&value(&arg)),
+                "--glob" => res.tree = res.tree.clone().with_glob(&value(&arg)),
+                "--no-copy" => res.tree.copy_others = false,
+                "-j" | "--jobs" => {
+                    let jobs = value(&arg);
+                    res.tree.threads = jobs.parse().unwrap_or_else(|_| {
+                        usage_error(&format!("invalid number of jobs `{}`", jobs))
+                    })
+                }
+                "-i" | "--repl" => res.repl = true,
+                "-w" | "--watch" => res.watch = true,
+                "--interval" => {
+                    let ms = value(&arg);
+                    res.interval = ms
+                        .parse()
+                        .map(Duration::from_millis)
+                        .unwrap_or_else(|_| usage_error(&format!("invalid interval `{}`", ms)))
+                }
+                "--dry-run" => {
+                    res.dry_run.get_or_insert_with(DryRun::new);
+                }
+                "--placeholder" => {
+                    let def = value(&arg);
+                    let (cmd, text) = def.split_once('=').unwrap_or_else(|| {
+                        usage_error(&format!("expected CMD=TEXT, got `{}`", def))
+                    });
+                    let dry_run = res.dry_run.get_or_insert_with(DryRun::new);
+                    match cmd {
+                        "run" => dry_run.run_output = text.to_string(),
+                        "include" => dry_run.include_output = text.to_string(),
+                        "lsdir" => dry_run.lsdir_output = text.to_string(),
+                        _ => usage_error(&format!("no placeholder for `{}`", cmd)),
+                    }
+                }
+                "--format" => res.format = true,
+                "--lint" => res.lint = true,
+                "--check" => res.check = true,
+                "--indent" => {
+                    let n = value(&arg);
+                    res.indent = Some(match &n[..] {
+                        "tab" => "\t".to_string(),
+                        _ => " ".repeat(n.parse().unwrap_or_else(|_| {
+                            usage_error(&format!("invalid indentation `{}`", n))
+                        })),
+                    })
+                }
+                "-" => res.set_input(PathBuf::from("-")),
+                s if s.starts_with('-') => usage_error(&format!("unknown option `{}`", s)),
+                s => res.set_input(PathBuf::from(s)),
+            }
+        }
+        res
+    }
+
+    fn set_input(&mut self, path: PathBuf) {
+        if self.input.is_some() {
+            usage_error("more than one input given");
+        }
+        self.input = Some(path).filter(|p| p != Path::new("-"));
+    }
+}
+
+/// Converts a byte index into a position, clamping it to the source
+fn position(i: usize, src: &str) -> RowCol {
+    let mut i = i.min(src.len());
+    while !src.is_char_boundary(i) {
+        i -= 1;
+    }
+    RowCol::from_index(i, src)
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+fn print_issue(issue: &Issue, src: &str, file: &str, format: DiagnosticsFormat) {
+    let severity = severity_name(issue.severity());
+    let start = position(issue.span.start, src);
+    let end = position(issue.span.end(), src);
+    match format {
+        DiagnosticsFormat::Human => {
+            eprintln!("{}: {}", severity, issue.msg);
+            eprintln!("  --> {}:{} [{}]", file, start, issue.id);
+        }
+        DiagnosticsFormat::Short => {
+            eprintln!(
+                "{}:{}: {}: {} [{}]",
+                file, start, severity, issue.msg, issue.id
+            )
+        }
+        DiagnosticsFormat::Json => {
+            let json = |s: &str| Escaper::JsonStr.escape(s);
+            eprintln!(
+                "{{\"severity\":\"{}\",\"id\":\"{}\",\"message\":\"{}\",\"file\":\"{}\",\
+                 \"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}}}}",
+                severity,
+                json(issue.id),
+                json(&issue.msg),
+                json(file),
+                start.row + 1,
+                start.col + 1,
+                end.row + 1,
+                end.col + 1,
+            );
+        }
+    }
+}
+
+/// Creates the engine with the enabled commands and all variables
+fn build_engine(opts: &Options, root: Option<PathBuf>) -> Engine<Free> {
+    let mut en = Engine::new(HashMap::new());
+    if let Some(root) = root {
+        en = en.with_root_path(root);
+    }
+    en.add_commands(get_handlers(&opts.commands))
+        .expect("internal error: default command names invalid");
+    for path in &opts.var_files {
+        let format = DataFormat::from_path(path)
+            .unwrap_or_else(|| fatal(&format!("{}: unknown data format", path.display())));
+        // relative to the current directory, not the root path
+        let path = std::env::current_dir()
+            .map(|d| d.join(path))
+            .unwrap_or_else(|_| path.clone());
+        if let Err(e) = en.load_data(&path, format, "") {
+            fatal(&e.to_string());
+        }
+    }
+    en.vars.extend(opts.defines.iter().cloned());
+    match &opts.dry_run {
+        Some(dry_run) => en.with_dry_run(dry_run.clone()),
+        None => en,
+    }
+}
+
+/// Prints a side effect that was recorded in a dry run
+fn print_side_effect(effect: &SideEffect, format: DiagnosticsFormat) {
+    match format {
+        DiagnosticsFormat::Json => eprintln!(
+            "{{\"side_effect\":\"{}\"}}",
+            Escaper::JsonStr.escape(&effect.to_string())
+        ),
+        _ => eprintln!("ppm: would {}", effect),
+    }
+}
+
+fn read_input(opts: &Options) -> String {
+    match &opts.input {
+        Some(path) => std::fs::read_to_string(path)
+            .unwrap_or_else(|e| fatal(&format!("{}: {}", path.display(), e))),
+        None => {
+            let mut s = String::new();
+            std::io::stdin()
+                .read_to_string(&mut s)
+                .unwrap_or_else(|e| fatal(&format!("<stdin>: {}", e)));
+            s
+        }
+    }
+}
+
+fn input_name(opts: &Options) -> String {
+    opts.input
+        .as_ref()
+        .map_or_else(|| "<stdin>".to_string(), |p| p.display().to_string())
+}
+
+/// Writes to the output file (if any) or stdout
+fn write_output(path: Option<&Path>, output: &str) {
+    let written = match path {
+        Some(path) => {
+            std::fs::write(path, output).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(output.as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|e| format!("<stdout>: {}", e))
+        }
+    };
+    if let Err(e) = written {
+        fatal(&e);
+    }
+}
+
+/// Formats a single template (or checks whether it is formatted), returning whether that failed
+fn format_file(opts: &Options) -> bool {
+    let src = read_input(opts);
+    let mut options = FormatOptions::default();
+    if let Some(indent) = &opts.indent {
+        options = options.with_indent(indent);
+    }
+    match format_template(&src, &options) {
+        Ok(formatted) if opts.check => {
+            if formatted != src {
+                eprintln!("ppm: {} is not formatted", input_name(opts));
+            }
+            formatted != src
+        }
+        Ok(formatted) => {
+            write_output(opts.output.as_deref(), &formatted);
+            false
+        }
+        Err(issues) => {
+            for issue in &issues {
+                print_issue(issue, &src, &input_name(opts), opts.diagnostics);
+            }
+            true
+        }
+    }
+}
+
+/// Lints a single template, returning the highest severity of its issues
+fn lint_file(opts: &Options) -> Option<Severity> {
+    let src = read_input(opts);
+    let issues = build_engine(opts, opts.root.clone()).lint(&src);
+    for issue in &issues {
+        print_issue(issue, &src, &input_name(opts), opts.diagnostics);
+    }
+    issues.iter().map(Issue::severity).max()
+}
+
+/// Renders a single template, returning the highest severity of its issues
+///
+/// The engine is returned as well, it knows which files were accessed
+fn render_file(opts: &Options) -> (Option<Severity>, Engine<Free>) {
+    let src = read_input(opts);
+    let root = match (&opts.root, &opts.input) {
+        (Some(root), _) => Some(root.clone()),
+        (None, Some(input)) => input
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_path_buf),
+        (None, None) => None,
+    };
+
+    let mut en = build_engine(opts, root);
+    let (output, issues) = en.process_new(src.clone());
+
+    let file = input_name(opts);
+    for issue in &issues {
+        print_issue(issue, &src, &file, opts.diagnostics);
+    }
+
+    if opts.dry_run.is_some() {
+        for effect in en.audit_log() {
+            print_side_effect(effect, opts.diagnostics);
+        }
+        if let Some(path) = &opts.output {
+            print_side_effect(&SideEffect::WriteFile(path.clone()), opts.diagnostics);
+        }
+        write_output(None, &output);
+    } else {
+        write_output(opts.output.as_deref(), &output);
+    }
+    (issues.iter().map(Issue::severity).max(), en)
+}
+
+/// Renders a single template again whenever it or a file it accessed changes
+fn watch_file(opts: &Options) -> ! {
+    let input = opts
+        .input
+        .clone()
+        .unwrap_or_else(|| usage_error("can't watch stdin"));
+    loop {
+        // watched before rendering, so that changes made meanwhile are not missed
+        let mut files = FileWatcher::new();
+        files.watch(input.clone());
+        let start = SystemTime::now();
+        let (_, en) = render_file(opts);
+        for path in en.accessed_files() {
+            files.watch_since(path.clone(), start);
+        }
+        while files.poll().is_empty() {
+            std::thread::sleep(opts.interval);
+        }
+        eprintln!("ppm: change detected, rendering {}", input.display());
+    }
+}
+
+/// Prints the issues of a report, returning their highest severity
+fn print_report(opts: &Options, input: &Path, report: &TreeReport) -> Option<Severity> {
+    for (file, issue) in report.issues() {
+        let path = input.join(&file.input).display().to_string();
+        let src = file.template.as_deref().unwrap_or("");
+        print_issue(issue, src, &path, opts.diagnostics);
+    }
+    for (_, effect) in report.side_effects() {
+        print_side_effect(effect, opts.diagnostics);
+    }
+    report.max_severity()
+}
+
+/// Renders a directory tree, returning the highest severity of all issues
+///
+/// If watching, this only returns on errors
+fn render_tree(opts: &Options, input: &Path) -> Option<Severity> {
+    let output = opts
+        .output
+        .as_ref()
+        .unwrap_or_else(|| usage_error("rendering a directory requires --output"));
+    let mut tree_opts = opts.tree.clone();
+    if tree_opts.extensions.is_empty() && tree_opts.globs.is_empty() {
+        tree_opts = tree_opts.with_extension("ppm");
+    }
+
+    let mut en = build_engine(opts, opts.root.clone());
+    if !opts.watch {
+        let report = en
+            .process_tree(input, output, &tree_opts)
+            .unwrap_or_else(|e| fatal(&format!("{}: {}", input.display(), e)));
+        return print_report(opts, input, &report);
+    }
+
+    // the data files are not accessed by the templates, so they are watched separately
+    let mut var_files = FileWatcher::new();
+    for path in en.take_accessed_files() {
+        var_files.watch(path);
+    }
+    let mut watcher = TreeWatcher::new(input.to_path_buf(), output.clone(), tree_opts);
+    loop {
+        if !var_files.poll().is_empty() {
+            en = build_engine(opts, opts.root.clone());
+            en.take_accessed_files();
+            watcher.invalidate();
+        }
+        let report = watcher
+            .update(&en)
+            .unwrap_or_else(|e| fatal(&format!("{}: {}", input.display(), e)));
+        if !report.files.is_empty() {
+            print_report(opts, input, &report);
+            eprintln!("ppm: updated {} file(s)", report.files.len());
+        }
+        std::thread::sleep(opts.interval);
+    }
+}
+
+fn main() {
+    let opts = Options::parse(std::env::args().skip(1));
+
+    if opts.repl {
+        if opts.input.is_some() {
+            usage_error("--repl doesn't take an input");
+        }
+        repl::run(build_engine(&opts, opts.root.clone()), opts.diagnostics);
+        return;
+    }
+    if opts.format {
+        if matches!(&opts.input, Some(p) if p.is_dir()) {
+            usage_error("--format can't format a directory");
+        }
+        if format_file(&opts) {
+            exit(1);
+        }
+        return;
+    }
+    if opts.watch && opts.dry_run.is_some() {
+        usage_error("--watch can't be combined with --dry-run");
+    }
+    let severity = match &opts.input {
+        Some(input) if input.is_dir() && opts.lint => usage_error("--lint can't lint a directory"),
+        _ if opts.lint => lint_file(&opts),
+        Some(input) if input.is_dir() => render_tree(&opts, input),
+        _ if opts.watch => watch_file(&opts),
+        _ => render_file(&opts).0,
+    };
+    let failed = match (opts.fail_on, severity) {
+        (Some(min), Some(severity)) => severity >= min,
+        _ => false,
+    };
+    if failed {
+        exit(1);
+    }
+}
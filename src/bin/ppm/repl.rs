@@ -0,0 +1,119 @@
+//! The interactive mode of the `ppm` tool
+
+use crate::{print_issue, DiagnosticsFormat};
+use ppm::{Engine, Free};
+use std::io::{BufRead, IsTerminal, Write};
+
+const HELP: &str = "\
+Every entry is processed by the same engine, so variables set with `let` stay set.
+An entry continues on the next line as long as it has unclosed `(%`.
+
+Meta-commands:
+  :vars [PREFIX]     list all variables (whose names start with PREFIX)
+  :set NAME=VALUE    set a variable
+  :unset NAME        remove a variable
+  :commands          list all registered commands
+  :help              print this help
+  :quit              exit (so does end of input)
+  ::TEXT             process `:TEXT` instead of running a meta-command";
+
+/// Runs a meta-command (without the leading `:`), returning `false` if the REPL should exit
+fn meta_command(en: &mut Engine<Free>, cmd: &str) -> bool {
+    let (name, arg) = match cmd.find(char::is_whitespace) {
+        Some(i) => (&cmd[..i], cmd[i..].trim()),
+        None => (cmd, ""),
+    };
+    match name {
+        "vars" => {
+            let mut vars = en
+                .vars
+                .iter()
+                .filter(|(k, _)| k.starts_with(arg))
+                .collect::<Vec<_>>();
+            vars.sort();
+            for (k, v) in vars {
+                println!("{} = {:?}", k, v);
+            }
+        }
+        "set" => match arg.find('=') {
+            Some(i) => {
+                en.vars
+                    .insert(arg[..i].trim().to_string(), arg[i + 1..].to_string());
+            }
+            None => eprintln!("expected NAME=VALUE"),
+        },
+        "unset" => {
+            if en.vars.remove(arg).is_none() {
+                eprintln!("`{}` is not set", arg);
+            }
+        }
+        "commands" => {
+            // the command with the empty name is what `(%name%)` calls
+            let names = en.command_names();
+            let names = names.iter().map(|&n| if n.is_empty() { "\"\"" } else { n });
+            println!("{}", names.collect::<Vec<_>>().join(" "));
+        }
+        "help" => println!("{}", HELP),
+        "quit" | "q" | "exit" => return false,
+        _ => eprintln!("unknown meta-command `:{}`, see `:help`", name),
+    }
+    true
+}
+
+/// Reads entries from stdin until the end of input or `:quit`
+pub fn run(mut en: Engine<Free>, diagnostics: DiagnosticsFormat) {
+    let interactive = std::io::stdin().is_terminal();
+    let prompt = |s: &str| {
+        if interactive {
+            print!("{}", s);
+            let _ = std::io::stdout().flush();
+        }
+    };
+    if interactive {
+        println!("ppm {} - type `:help` for help", env!("CARGO_PKG_VERSION"));
+    }
+
+    let mut lines = std::io::stdin().lock().lines();
+    let mut entry = String::new();
+    loop {
+        prompt(if entry.is_empty() { "ppm> " } else { "...> " });
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("ppm: <stdin>: {}", e);
+                break;
+            }
+            None => break,
+        };
+
+        if entry.is_empty() {
+            if let Some(cmd) = line.strip_prefix(':') {
+                if !cmd.starts_with(':') {
+                    if meta_command(&mut en, cmd.trim()) {
+                        continue;
+                    }
+                    break;
+                }
+                entry.push_str(cmd);
+            } else {
+                entry.push_str(&line);
+            }
+        } else {
+            entry.push('\n');
+            entry.push_str(&line);
+        }
+        if Engine::open_commands(&entry) > 0 {
+            continue;
+        }
+
+        let src = std::mem::take(&mut entry);
+        let (output, issues) = en.process_new(src.clone());
+        println!("{}", output);
+        for issue in &issues {
+            print_issue(issue, &src, "<repl>", diagnostics);
+        }
+    }
+    if !entry.is_empty() {
+        eprintln!("ppm: discarding unfinished entry");
+    }
+}
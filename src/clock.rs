@@ -0,0 +1,52 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The source of the current time for commands that need it
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Clock {
+    /// The system clock
+    ///
+    /// If the environment variable `SOURCE_DATE_EPOCH` is set to a unix timestamp
+    /// (and the sandbox allows reading environment variables), that time is used instead,
+    /// see [reproducible-builds.org](https://reproducible-builds.org/specs/source-date-epoch/)
+    System,
+    /// A fixed point in time, useful for tests
+    Fixed(SystemTime),
+}
+
+impl Default for Clock {
+    #[inline]
+    fn default() -> Self {
+        Clock::System
+    }
+}
+
+impl Clock {
+    /// Creates a fixed clock from a unix timestamp (in seconds)
+    #[inline]
+    pub fn fixed_unix(secs: i64) -> Self {
+        Clock::Fixed(unix_to_system_time(secs))
+    }
+
+    /// The current time according to this clock
+    ///
+    /// `allow_env` specifies whether `SOURCE_DATE_EPOCH` may be read
+    pub fn now(&self, allow_env: bool) -> SystemTime {
+        match *self {
+            Clock::Fixed(t) => t,
+            Clock::System => allow_env
+                .then(|| std::env::var("SOURCE_DATE_EPOCH").ok())
+                .flatten()
+                .and_then(|s| s.trim().parse::<i64>().ok())
+                .map_or_else(SystemTime::now, unix_to_system_time),
+        }
+    }
+}
+
+#[inline]
+fn unix_to_system_time(secs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    }
+}
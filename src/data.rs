@@ -0,0 +1,259 @@
+use crate::predefined_commands::join_reescape_colon;
+use crate::util::make_absolute;
+use crate::{Engine, Free, RowCol};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+mod csv;
+mod dotenv;
+mod ini;
+#[cfg(feature = "serde_json")]
+mod json;
+#[cfg(feature = "toml")]
+mod toml;
+
+pub use self::csv::CsvOptions;
+
+/// The formats data files can be in
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DataFormat {
+    /// JSON (requires the `serde_json` feature)
+    Json,
+    /// TOML (requires the `toml` feature)
+    Toml,
+    /// INI files, where sections become objects
+    Ini,
+    /// `.env` files, consisting of `KEY=value` lines
+    Env,
+    /// CSV files, which become an array of records
+    Csv(CsvOptions),
+}
+
+impl DataFormat {
+    /// Guesses the format of a file from its name
+    ///
+    /// Recognized are the extensions `json`, `toml`, `ini`, `env` and `csv`, as well as files named `.env`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        if path.file_name() == Some(".env".as_ref()) {
+            return Some(DataFormat::Env);
+        }
+        match path.extension()?.to_str()? {
+            "json" => Some(DataFormat::Json),
+            "toml" => Some(DataFormat::Toml),
+            "ini" => Some(DataFormat::Ini),
+            "env" => Some(DataFormat::Env),
+            "csv" => Some(DataFormat::Csv(CsvOptions::default())),
+            _ => None,
+        }
+    }
+
+    /// Parses a document in this format
+    pub fn parse(self, src: &str) -> Result<DataValue, DataError> {
+        match self {
+            #[cfg(feature = "serde_json")]
+            DataFormat::Json => json::parse_json(src),
+            #[cfg(feature = "toml")]
+            DataFormat::Toml => self::toml::parse_toml(src),
+            DataFormat::Ini => ini::parse_ini(src),
+            DataFormat::Env => dotenv::parse_dotenv(src),
+            DataFormat::Csv(options) => self::csv::parse_csv(src, options),
+            #[allow(unreachable_patterns)]
+            f => Err(DataError::Parse {
+                path: None,
+                pos: None,
+                msg: format!("support for {:?} is not enabled", f),
+            }),
+        }
+    }
+}
+
+/// Removes the position information some parsers append to their error messages
+#[cfg(any(feature = "serde_json", feature = "toml"))]
+fn strip_position_suffix(msg: &mut String, line: usize, col: usize) {
+    let suffix = format!(" at line {} column {}", line, col);
+    if msg.ends_with(&suffix) {
+        msg.truncate(msg.len() - suffix.len());
+    }
+}
+
+/// A structured value loaded from a data file
+///
+/// When inserted into an engine, a value is flattened into variables:
+/// - scalars are stored as-is (`null` becomes the empty string)
+/// - the elements of arrays and objects are stored under `<name>.<index>` and `<name>.<key>` respectively,
+///   and the variable `<name>` itself holds a colon-separated list of the indices/keys
+///     - such a variable can be iterated with `(%for x of <name>:...%)` (see [`for_handler`](predefined_commands/fn.for_handler.html))
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DataValue {
+    /// An absent value
+    Null,
+    /// A boolean
+    Bool(bool),
+    /// A number, stored as it was written
+    Number(String),
+    /// A string
+    String(String),
+    /// An ordered list of values
+    Array(Vec<DataValue>),
+    /// An ordered list of key-value pairs
+    Object(Vec<(String, DataValue)>),
+}
+
+impl DataValue {
+    /// Inserts the flattened form of `self` into `vars`, with all variable names prefixed by `prefix`
+    ///
+    /// If `prefix` is empty, the elements of an array or object are stored at the top level
+    pub fn flatten_into(&self, prefix: &str, vars: &mut HashMap<String, String>) {
+        let child = |k: &str| {
+            if prefix.is_empty() {
+                k.to_string()
+            } else {
+                format!("{}.{}", prefix, k)
+            }
+        };
+        let val = match self {
+            DataValue::Null => String::new(),
+            DataValue::Bool(b) => b.to_string(),
+            DataValue::Number(s) | DataValue::String(s) => s.clone(),
+            DataValue::Array(v) => {
+                for (i, x) in v.iter().enumerate() {
+                    x.flatten_into(&child(&i.to_string()), vars);
+                }
+                join_reescape_colon((0..v.len()).map(|i| i.to_string()))
+            }
+            DataValue::Object(v) => {
+                for (k, x) in v {
+                    x.flatten_into(&child(k), vars);
+                }
+                join_reescape_colon(v.iter().map(|(k, _)| k.clone()))
+            }
+        };
+        if !prefix.is_empty() {
+            vars.insert(prefix.to_string(), val);
+        }
+    }
+}
+
+/// An error encountered while loading a data file
+#[derive(Debug)]
+pub enum DataError {
+    /// The file could not be read
+    Io(PathBuf, std::io::Error),
+    /// The file could not be parsed
+    Parse {
+        /// The file, if the data came from one
+        path: Option<PathBuf>,
+        /// The position of the error in the file, if known
+        pos: Option<RowCol>,
+        /// A message that describes the error
+        msg: String,
+    },
+}
+
+impl DataError {
+    #[inline]
+    fn at(row: usize, col: usize, msg: &str) -> Self {
+        DataError::Parse {
+            path: None,
+            pos: Some(RowCol { row, col }),
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl Display for DataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            DataError::Parse { path, pos, msg } => {
+                match path {
+                    Some(p) => write!(f, "{}", p.display())?,
+                    None => f.write_str("<input>")?,
+                }
+                if let Some(pos) = pos {
+                    write!(f, ":{}", pos)?;
+                }
+                write!(f, ": {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataError {}
+
+/// Reads and parses a data file, resolving relative paths against `root_path`
+pub(crate) fn read_data_file(
+    path: &Path,
+    root_path: Option<PathBuf>,
+    format: DataFormat,
+) -> Result<DataValue, DataError> {
+    let path = make_absolute(path, root_path).map_err(|e| DataError::Io(path.to_path_buf(), e))?;
+    let src = std::fs::read_to_string(&path).map_err(|e| DataError::Io(path.clone(), e))?;
+    format.parse(&src).map_err(|e| match e {
+        DataError::Parse { pos, msg, .. } => DataError::Parse {
+            path: Some(path),
+            pos,
+            msg,
+        },
+        e => e,
+    })
+}
+
+impl Engine<Free> {
+    /// Inserts a structured value into the engine's variables, under the name `prefix`
+    ///
+    /// See [`DataValue`](enum.DataValue.html) for how the value is flattened
+    #[inline]
+    pub fn insert_data(&mut self, prefix: &str, value: &DataValue) {
+        value.flatten_into(prefix, &mut self.vars);
+    }
+
+    /// Loads a data file into the engine's variables, under the name `prefix`
+    ///
+    /// Relative paths are relative to the root path
+    pub fn load_data<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        format: DataFormat,
+        prefix: &str,
+    ) -> Result<(), DataError> {
+        if let Ok(path) = make_absolute(path.as_ref(), self.root_path.clone()) {
+            self.accessed_files.insert(path);
+        }
+        let value = read_data_file(path.as_ref(), self.root_path.clone(), format)?;
+        self.insert_data(prefix, &value);
+        Ok(())
+    }
+
+    /// Parses a document and inserts it into the engine's variables, under the name `prefix`
+    #[inline]
+    pub fn load_data_str(
+        &mut self,
+        src: &str,
+        format: DataFormat,
+        prefix: &str,
+    ) -> Result<(), DataError> {
+        let value = format.parse(src)?;
+        self.insert_data(prefix, &value);
+        Ok(())
+    }
+
+    /// (requires the `serde_json` feature) loads a JSON file into the engine's variables, under the name `prefix`
+    ///
+    /// Relative paths are relative to the root path
+    #[cfg(feature = "serde_json")]
+    #[inline]
+    pub fn load_json<P: AsRef<Path>>(&mut self, path: P, prefix: &str) -> Result<(), DataError> {
+        self.load_data(path, DataFormat::Json, prefix)
+    }
+
+    /// (requires the `serde_json` feature) parses a JSON document and inserts it into the engine's variables,
+    /// under the name `prefix`
+    #[cfg(feature = "serde_json")]
+    #[inline]
+    pub fn load_json_str(&mut self, src: &str, prefix: &str) -> Result<(), DataError> {
+        self.load_data_str(src, DataFormat::Json, prefix)
+    }
+}
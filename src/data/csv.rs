@@ -0,0 +1,124 @@
+use super::{DataError, DataValue};
+
+/// Options for parsing CSV files
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct CsvOptions {
+    /// The character that separates fields
+    pub delimiter: char,
+    /// Whether the first record holds the names of the columns
+    pub has_header: bool,
+}
+
+impl Default for CsvOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            has_header: true,
+        }
+    }
+}
+
+fn parse_records(src: &str, delim: char) -> Result<Vec<Vec<String>>, DataError> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut row = 0;
+    let mut col = 0;
+    let mut iter = src.chars().peekable();
+    // whether the current field has been started (i.e. there is a field, even if it is empty)
+    let mut started = false;
+    while let Some(c) = iter.next() {
+        match c {
+            '"' if field.is_empty() => {
+                let (start_row, start_col) = (row, col);
+                loop {
+                    match iter.next() {
+                        Some('"') if iter.peek() == Some(&'"') => {
+                            iter.next();
+                            col += 2;
+                            field.push('"');
+                        }
+                        Some('"') => {
+                            col += 1;
+                            break;
+                        }
+                        Some('\n') => {
+                            row += 1;
+                            col = 0;
+                            field.push('\n');
+                        }
+                        Some(c) => {
+                            col += 1;
+                            field.push(c);
+                        }
+                        None => {
+                            return Err(DataError::at(
+                                start_row,
+                                start_col,
+                                "unterminated quoted field",
+                            ))
+                        }
+                    }
+                }
+                started = true;
+            }
+            c if c == delim => {
+                record.push(std::mem::take(&mut field));
+                started = true;
+            }
+            '\r' if iter.peek() == Some(&'\n') => (),
+            '\n' => {
+                if started || !field.is_empty() {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                started = false;
+                row += 1;
+                col = 0;
+                continue;
+            }
+            c => {
+                field.push(c);
+                started = true;
+            }
+        }
+        col += 1;
+    }
+    if started || !field.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Parses a CSV file
+///
+/// - with a header, every record becomes an object keyed by the column names
+/// - without a header, every record becomes an array
+/// - fields may be quoted with `"`, in which case they may contain the delimiter, newlines and `""` for a literal `"`
+pub fn parse_csv(src: &str, options: CsvOptions) -> Result<DataValue, DataError> {
+    let mut records = parse_records(src, options.delimiter)?.into_iter();
+    let to_value = |s: String| DataValue::String(s);
+    if !options.has_header {
+        return Ok(DataValue::Array(
+            records
+                .map(|r| DataValue::Array(r.into_iter().map(to_value).collect()))
+                .collect(),
+        ));
+    }
+    let header = records.next().unwrap_or_default();
+    Ok(DataValue::Array(
+        records
+            .map(|r| {
+                let mut fields = r.into_iter();
+                DataValue::Object(
+                    header
+                        .iter()
+                        .map(|h| (h.clone(), to_value(fields.next().unwrap_or_default())))
+                        .collect(),
+                )
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,67 @@
+use super::{DataError, DataValue};
+
+fn parse_double_quoted(s: &str) -> Option<String> {
+    let mut res = String::new();
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        match c {
+            '"' => return Some(res),
+            '\\' => match iter.next()? {
+                'n' => res.push('\n'),
+                'r' => res.push('\r'),
+                't' => res.push('\t'),
+                c => res.push(c),
+            },
+            c => res.push(c),
+        }
+    }
+    None
+}
+
+/// Parses a `.env` file
+///
+/// - entries have the form `KEY=value`, optionally preceded by `export`
+/// - values may be enclosed in single quotes (taken literally) or double quotes (supporting `\n`, `\t`, `\r` and `\\` escapes)
+/// - lines starting with `#` are comments, as is anything after ` #` in an unquoted value
+pub fn parse_dotenv(src: &str) -> Result<DataValue, DataError> {
+    let mut res: Vec<(String, DataValue)> = vec![];
+    for (row, line) in src.lines().enumerate() {
+        let trimmed = line.trim();
+        let col = line.len() - line.trim_start().len();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let entry = trimmed
+            .strip_prefix("export ")
+            .map_or(trimmed, str::trim_start);
+        let eq = match entry.find('=') {
+            Some(i) => i,
+            None => return Err(DataError::at(row, col, "expected `KEY=value`")),
+        };
+        let key = entry[..eq].trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(DataError::at(row, col, &format!("invalid key `{}`", key)));
+        }
+        let raw = entry[eq + 1..].trim();
+        let val = if let Some(rest) = raw.strip_prefix('"') {
+            match parse_double_quoted(rest) {
+                Some(s) => s,
+                None => return Err(DataError::at(row, col, "unterminated string")),
+            }
+        } else if let Some(rest) = raw.strip_prefix('\'') {
+            match rest.find('\'') {
+                Some(i) => rest[..i].to_string(),
+                None => return Err(DataError::at(row, col, "unterminated string")),
+            }
+        } else {
+            raw.find(" #")
+                .map_or(raw, |i| raw[..i].trim_end())
+                .to_string()
+        };
+        match res.iter_mut().find(|(k, _)| k == key) {
+            Some(e) => e.1 = DataValue::String(val),
+            None => res.push((key.to_string(), DataValue::String(val))),
+        }
+    }
+    Ok(DataValue::Object(res))
+}
@@ -0,0 +1,64 @@
+use super::{DataError, DataValue};
+
+fn unquote(s: &str) -> &str {
+    let quoted = s.len() >= 2
+        && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')));
+    if quoted {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+fn set(entries: &mut Vec<(String, DataValue)>, key: &str, val: DataValue) {
+    match entries.iter_mut().find(|(k, _)| k == key) {
+        Some(e) => e.1 = val,
+        None => entries.push((key.to_string(), val)),
+    }
+}
+
+/// Parses an INI file
+///
+/// - sections (`[name]`) become objects, keys before the first section are at the top level
+/// - entries have the form `key = value` or `key: value`, quotes around values are removed
+/// - lines starting with `;` or `#` are comments
+pub fn parse_ini(src: &str) -> Result<DataValue, DataError> {
+    let mut root: Vec<(String, DataValue)> = vec![];
+    let mut section: Option<usize> = None;
+    for (row, line) in src.lines().enumerate() {
+        let trimmed = line.trim();
+        let col = line.len() - line.trim_start().len();
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed.starts_with('[') {
+            if !trimmed.ends_with(']') {
+                return Err(DataError::at(row, col, "unterminated section header"));
+            }
+            let name = trimmed[1..trimmed.len() - 1].trim();
+            let existing = root
+                .iter()
+                .position(|(k, v)| k == name && matches!(v, DataValue::Object(_)));
+            section = Some(existing.unwrap_or_else(|| {
+                root.push((name.to_string(), DataValue::Object(vec![])));
+                root.len() - 1
+            }));
+            continue;
+        }
+        let sep = match trimmed.find(&['=', ':'][..]) {
+            Some(i) => i,
+            None => return Err(DataError::at(row, col, "expected `key = value`")),
+        };
+        let key = trimmed[..sep].trim();
+        if key.is_empty() {
+            return Err(DataError::at(row, col, "empty key"));
+        }
+        let val = DataValue::String(unquote(trimmed[sep + 1..].trim()).to_string());
+        let entries = match section.map(|i| &mut root[i].1) {
+            Some(DataValue::Object(v)) => v,
+            _ => &mut root,
+        };
+        set(entries, key, val);
+    }
+    Ok(DataValue::Object(root))
+}
@@ -0,0 +1,32 @@
+use super::{DataError, DataValue};
+use serde_json::Value;
+
+fn convert(v: Value) -> DataValue {
+    match v {
+        Value::Null => DataValue::Null,
+        Value::Bool(b) => DataValue::Bool(b),
+        Value::Number(n) => DataValue::Number(n.to_string()),
+        Value::String(s) => DataValue::String(s),
+        Value::Array(v) => DataValue::Array(v.into_iter().map(convert).collect()),
+        Value::Object(m) => {
+            DataValue::Object(m.into_iter().map(|(k, v)| (k, convert(v))).collect())
+        }
+    }
+}
+
+pub fn parse_json(src: &str) -> Result<DataValue, DataError> {
+    serde_json::from_str(src).map(convert).map_err(|e| {
+        let mut msg = e.to_string();
+        // serde_json reports 1-based positions and uses line 0 if there is no position
+        if e.line() == 0 {
+            DataError::Parse {
+                path: None,
+                pos: None,
+                msg,
+            }
+        } else {
+            super::strip_position_suffix(&mut msg, e.line(), e.column());
+            DataError::at(e.line() - 1, e.column().saturating_sub(1), &msg)
+        }
+    })
+}
@@ -0,0 +1,31 @@
+use super::{DataError, DataValue};
+use ::toml::Value;
+
+fn convert(v: Value) -> DataValue {
+    match v {
+        Value::String(s) => DataValue::String(s),
+        Value::Integer(n) => DataValue::Number(n.to_string()),
+        Value::Float(n) => DataValue::Number(n.to_string()),
+        Value::Boolean(b) => DataValue::Bool(b),
+        Value::Datetime(d) => DataValue::String(d.to_string()),
+        Value::Array(v) => DataValue::Array(v.into_iter().map(convert).collect()),
+        Value::Table(m) => DataValue::Object(m.into_iter().map(|(k, v)| (k, convert(v))).collect()),
+    }
+}
+
+pub fn parse_toml(src: &str) -> Result<DataValue, DataError> {
+    src.parse::<Value>().map(convert).map_err(|e| {
+        let mut msg = e.to_string();
+        match e.line_col() {
+            Some((row, col)) => {
+                super::strip_position_suffix(&mut msg, row + 1, col + 1);
+                DataError::at(row, col, &msg)
+            }
+            None => DataError::Parse {
+                path: None,
+                pos: None,
+                msg,
+            },
+        }
+    })
+}
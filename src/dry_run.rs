@@ -0,0 +1,108 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+/// Something a render would do outside of the engine, recorded instead of performed in a dry run
+/// (see [`Engine::with_dry_run`](struct.Engine.html#method.with_dry_run))
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum SideEffect {
+    /// Spawning a process (`run`)
+    Run {
+        /// The program to run
+        program: String,
+        /// The arguments passed to it
+        args: Vec<String>,
+        /// The working directory it would run in
+        dir: PathBuf,
+    },
+    /// Reading a file (`include`, the `load_*` commands, `csv` and `for ... in csv`)
+    ReadFile(PathBuf),
+    /// Listing a directory (`lsdir`)
+    ListDir(PathBuf),
+    /// Creating a directory (and its missing parents) for an output file
+    CreateDir(PathBuf),
+    /// Writing a rendered template
+    WriteFile(PathBuf),
+    /// Copying a file that is no template
+    CopyFile {
+        /// The file that would be copied
+        from: PathBuf,
+        /// Where it would be copied to
+        to: PathBuf,
+    },
+}
+
+impl Display for SideEffect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SideEffect::Run { program, args, dir } => {
+                write!(f, "run `{}", program)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, "` in {}", dir.display())
+            }
+            SideEffect::ReadFile(path) => write!(f, "read {}", path.display()),
+            SideEffect::ListDir(path) => write!(f, "list {}", path.display()),
+            SideEffect::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            SideEffect::WriteFile(path) => write!(f, "write {}", path.display()),
+            SideEffect::CopyFile { from, to } => {
+                write!(f, "copy {} to {}", from.display(), to.display())
+            }
+        }
+    }
+}
+
+/// What commands output instead of performing their side effects in a dry run
+/// (see [`Engine::with_dry_run`](struct.Engine.html#method.with_dry_run))
+///
+/// All placeholders are empty by default
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DryRun {
+    /// The output of `run`
+    pub run_output: String,
+    /// The output of `include`
+    pub include_output: String,
+    /// The output of `lsdir`
+    pub lsdir_output: String,
+}
+
+impl DryRun {
+    /// Creates a dry run with empty placeholders
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what `run` outputs
+    #[inline]
+    pub fn with_run_output(mut self, output: &str) -> Self {
+        self.run_output = output.to_string();
+        self
+    }
+
+    /// Sets what `include` outputs
+    #[inline]
+    pub fn with_include_output(mut self, output: &str) -> Self {
+        self.include_output = output.to_string();
+        self
+    }
+
+    /// Sets what `lsdir` outputs
+    #[inline]
+    pub fn with_lsdir_output(mut self, output: &str) -> Self {
+        self.lsdir_output = output.to_string();
+        self
+    }
+
+    /// The placeholder output of the command that would perform `effect`
+    ///
+    /// Data files are never read, so there is no placeholder for them
+    pub fn placeholder(&self, effect: &SideEffect) -> &str {
+        match effect {
+            SideEffect::Run { .. } => &self.run_output,
+            SideEffect::ReadFile(_) => &self.include_output,
+            SideEffect::ListDir(_) => &self.lsdir_output,
+            _ => "",
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::Write;
+
+/// Ways to escape a string so that it can be embedded into another language
+///
+/// None of the escapers add surrounding quotes, except for [`ShellQuote`](#variant.ShellQuote)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Escaper {
+    /// Escapes `&`, `<`, `>`, `"` and `'` as HTML character references
+    Html,
+    /// Escapes `&`, `<`, `>`, `"` and `'` as XML entities
+    Xml,
+    /// Escapes a string for use inside a JSON string literal
+    JsonStr,
+    /// Percent-encodes all bytes except for ASCII letters, digits and `-._~`
+    Url,
+    /// Quotes a string as a single argument for a POSIX shell
+    ShellQuote,
+    /// Escapes a string for use inside a C string literal
+    ///
+    /// All bytes that are not printable ASCII are written as octal escapes
+    CStr,
+    /// Escapes a string for use inside a Rust string literal
+    RustStr,
+}
+
+impl Escaper {
+    /// All escapers, in the order they are declared
+    pub const ALL: [Escaper; 7] = [
+        Escaper::Html,
+        Escaper::Xml,
+        Escaper::JsonStr,
+        Escaper::Url,
+        Escaper::ShellQuote,
+        Escaper::CStr,
+        Escaper::RustStr,
+    ];
+
+    /// The name of the escaper, which is also the name of the command for it
+    pub fn name(self) -> &'static str {
+        match self {
+            Escaper::Html => "html",
+            Escaper::Xml => "xml",
+            Escaper::JsonStr => "json_str",
+            Escaper::Url => "url",
+            Escaper::ShellQuote => "shell_quote",
+            Escaper::CStr => "c_str",
+            Escaper::RustStr => "rust_str",
+        }
+    }
+
+    /// Looks up an escaper by its [`name`](#method.name)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.name() == name)
+    }
+
+    /// Escapes a string
+    pub fn escape(self, s: &str) -> String {
+        match self {
+            Escaper::Html => escape_markup(s, "&#39;"),
+            Escaper::Xml => escape_markup(s, "&apos;"),
+            Escaper::JsonStr => escape_json(s),
+            Escaper::Url => escape_url(s),
+            Escaper::ShellQuote => quote_shell(s),
+            Escaper::CStr => escape_c(s),
+            Escaper::RustStr => s.escape_debug().to_string(),
+        }
+    }
+}
+
+fn escape_markup(s: &str, apos: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str(apos),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn escape_json(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            '\u{8}' => res.push_str("\\b"),
+            '\u{c}' => res.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(res, "\\u{:04x}", c as u32);
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn escape_url(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            res.push(b as char);
+        } else {
+            let _ = write!(res, "%{:02X}", b);
+        }
+    }
+    res
+}
+
+fn quote_shell(s: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !s.is_empty() && s.chars().all(is_safe) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn escape_c(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            b'\n' => res.push_str("\\n"),
+            b'\r' => res.push_str("\\r"),
+            b'\t' => res.push_str("\\t"),
+            // `?` could start a trigraph
+            b'?' => res.push_str("\\?"),
+            // octal escapes have at most 3 digits, so unlike `\x` they can't swallow the next character
+            b if !(0x20..0x7f).contains(&b) => {
+                let _ = write!(res, "\\{:03o}", b);
+            }
+            b => res.push(b as char),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escapers() {
+        let s = "a<b> & \"c'\n\u{1}ä?";
+        assert_eq!(
+            Escaper::Html.escape(s),
+            "a&lt;b&gt; &amp; &quot;c&#39;\n\u{1}ä?"
+        );
+        assert_eq!(Escaper::JsonStr.escape(s), "a<b> & \\\"c'\\n\\u0001ä?");
+        assert_eq!(
+            Escaper::Url.escape(s),
+            "a%3Cb%3E%20%26%20%22c%27%0A%01%C3%A4%3F"
+        );
+        assert_eq!(Escaper::ShellQuote.escape(s), "'a<b> & \"c'\\''\n\u{1}ä?'");
+        assert_eq!(Escaper::ShellQuote.escape("a/b.c"), "a/b.c");
+        assert_eq!(Escaper::ShellQuote.escape(""), "''");
+        assert_eq!(
+            Escaper::CStr.escape(s),
+            "a<b> & \\\"c'\\n\\001\\303\\244\\?"
+        );
+        assert_eq!(Escaper::RustStr.escape(s), "a<b> & \\\"c\\'\\n\\u{1}ä?");
+        for &e in Escaper::ALL.iter() {
+            assert_eq!(Escaper::from_name(e.name()), Some(e));
+        }
+    }
+}
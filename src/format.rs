@@ -0,0 +1,276 @@
+use crate::{trim, CommandNode, Issue, SyntaxNode, SyntaxTree};
+
+/// How [`format_template`](fn.format_template.html) formats a template
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FormatOptions {
+    /// The indentation of one nesting level, or `None` to keep the indentation
+    ///
+    /// Only lines whose leading whitespace is removed by trim markers anyway are re-indented
+    pub indent: Option<String>,
+    /// Commands whose bodies are not processed as templates, so they are kept as they are
+    pub verbatim_commands: Vec<String>,
+}
+
+impl Default for FormatOptions {
+    /// Keeps the indentation and treats the bodies of `lit`, `var`, `raw`, `safe`
+    /// and variables like `(%name%)` as verbatim
+    #[inline]
+    fn default() -> Self {
+        Self {
+            indent: None,
+            verbatim_commands: ["lit", "var", "raw", "safe", ""]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Re-indents nested lines with `indent` per nesting level
+    #[inline]
+    pub fn with_indent(mut self, indent: &str) -> Self {
+        self.indent = Some(indent.to_string());
+        self
+    }
+
+    /// Keeps the indentation (the default)
+    #[inline]
+    pub fn without_indent(mut self) -> Self {
+        self.indent = None;
+        self
+    }
+
+    /// Adds a command whose body is kept as it is
+    #[inline]
+    pub fn with_verbatim_command(mut self, name: &str) -> Self {
+        self.verbatim_commands.push(name.to_string());
+        self
+    }
+}
+
+struct Formatter<'a> {
+    src: &'a str,
+    options: &'a FormatOptions,
+    res: String,
+}
+
+impl Formatter<'_> {
+    /// Replaces whitespace containing a line break with the same number of line breaks
+    /// followed by the indentation of `depth`, if re-indenting is enabled
+    fn indent(&self, ws: &str, depth: usize) -> Option<String> {
+        let indent = self.options.indent.as_ref()?;
+        let breaks = ws.matches('\n').count();
+        if breaks == 0 {
+            return None;
+        }
+        Some("\n".repeat(breaks) + &indent.repeat(depth))
+    }
+
+    /// Emits text inside of a command (`depth > 0`) with canonical escapes, or other text as it is
+    ///
+    /// The engine drops the backslash of escaped invalid command characters (see
+    /// [`Engine::is_invalid_command_char`](struct.Engine.html#method.is_invalid_command_char)) inside of commands,
+    /// so escapes of `(`, `)`, `%`, `{` and `}` are only kept where the character would form a delimiter otherwise.
+    /// `next` is the character after `s` in the template
+    fn escaped(&mut self, s: &str, next: Option<char>, depth: usize) {
+        if depth == 0 {
+            self.res += s;
+            return;
+        }
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            let e = match chars.next_if(|_| c == '\\') {
+                Some(e) => e,
+                None => {
+                    self.res.push(c);
+                    continue;
+                }
+            };
+            let after = chars.peek().copied().or(next);
+            let needed = match e {
+                '(' => after == Some('%'),
+                '%' => self.res.ends_with('(') || after == Some(')'),
+                ')' => self.res.ends_with('%'),
+                '{' | '}' => false,
+                _ => true,
+            };
+            if needed {
+                self.res.push('\\');
+            }
+            self.res.push(e);
+        }
+    }
+
+    /// The character at `pos` in the template
+    #[inline]
+    fn char_at(&self, pos: usize) -> Option<char> {
+        self.src[pos..].chars().next()
+    }
+
+    /// Emits template text, re-indenting the whitespace that trim markers remove
+    ///
+    /// `next` is the character after `s` in the template
+    fn text(
+        &mut self,
+        s: &str,
+        (trimmed_start, trimmed_end): (bool, bool),
+        next: Option<char>,
+        depth: usize,
+    ) {
+        let start_len = if trimmed_start {
+            s.len() - s.trim_start().len()
+        } else {
+            0
+        };
+        if start_len == s.len() {
+            // only whitespace, which is either removed completely or not at all
+            match self
+                .indent(s, depth)
+                .filter(|_| trimmed_start || trimmed_end)
+            {
+                Some(ws) => self.res += &ws,
+                None => self.res += s,
+            }
+            return;
+        }
+        let end = if trimmed_end {
+            s.trim_end().len()
+        } else {
+            s.len()
+        };
+        match self.indent(&s[..start_len], depth) {
+            Some(ws) => self.res += &ws,
+            None => self.res += &s[..start_len],
+        }
+        self.escaped(&s[start_len..end], s[end..].chars().next().or(next), depth);
+        match self.indent(&s[end..], depth) {
+            Some(ws) => self.res += &ws,
+            None => self.res += &s[end..],
+        }
+    }
+
+    /// Emits the nodes between `start` and `end`, with the text in between
+    fn nodes(&mut self, nodes: &[SyntaxNode], start: usize, end: usize, depth: usize) {
+        let mut pos = start;
+        let mut trimmed = false;
+        for node in nodes {
+            let span = node.span();
+            let next_trimmed = match node {
+                SyntaxNode::Command(cmd) => trim::find_markers(self.inner(cmd)).0 > 0,
+                SyntaxNode::Comment(_) => false,
+            };
+            let next = self.char_at(span.start);
+            self.text(
+                &self.src[pos..span.start],
+                (trimmed, next_trimmed),
+                next,
+                depth,
+            );
+            match node {
+                SyntaxNode::Comment(span) => self.res += span.index_str(self.src),
+                SyntaxNode::Command(cmd) => {
+                    trimmed = trim::find_markers(self.inner(cmd)).1 > 0;
+                    self.command(cmd, depth);
+                }
+            }
+            if let SyntaxNode::Comment(_) = node {
+                trimmed = false;
+            }
+            pos = span.end();
+        }
+        let next = self.char_at(end);
+        self.text(&self.src[pos..end], (trimmed, false), next, depth);
+    }
+
+    /// The part of a command between the percent-parentheses
+    fn inner(&self, cmd: &CommandNode) -> &'_ str {
+        let s = cmd.span.index_str(self.src);
+        &s[2..s.len() - 2]
+    }
+
+    fn command(&mut self, cmd: &CommandNode, depth: usize) {
+        let (lead, trail) = trim::find_markers(self.inner(cmd));
+        self.res += "(%";
+        if lead > 0 {
+            self.res += "- ";
+        }
+        // the name is looked up as written, so nested commands in it make it unknown (unless it is a variable)
+        let in_name = cmd
+            .children
+            .iter()
+            .take_while(|n| n.span().start < cmd.body_span.start)
+            .count();
+        let (name_nodes, body_nodes) = cmd.children.split_at(in_name);
+        if name_nodes
+            .iter()
+            .any(|n| n.span().end() > cmd.name_span.end())
+        {
+            // a nested command contains the separator, so there is no name and body to format
+            let src = self.src;
+            self.res += &cmd.span.index_str(src)[2 + lead..];
+            return;
+        }
+        self.nodes(
+            name_nodes,
+            cmd.name_span.start,
+            cmd.name_span.end(),
+            depth + 1,
+        );
+        if cmd.body_span.len > 0 {
+            // the separator is always exactly one whitespace character, which is dropped
+            self.res.push(' ');
+            if self.options.verbatim_commands.contains(&cmd.name) {
+                let next = self.char_at(cmd.body_span.end());
+                self.escaped(cmd.body_span.index_str(self.src), next, depth + 1);
+            } else {
+                self.nodes(
+                    body_nodes,
+                    cmd.body_span.start,
+                    cmd.body_span.end(),
+                    depth + 1,
+                );
+            }
+        }
+        if trail > 0 {
+            let inner = self.inner(cmd);
+            let ws = &inner[inner.len() - trail..inner.len() - 1];
+            match self.indent(ws, depth) {
+                Some(ws) => self.res += &ws,
+                None => self.res.push(' '),
+            }
+            self.res.push('-');
+        }
+        self.res += "%)";
+    }
+}
+
+/// Formats a template, without changing what it renders to
+///
+/// - the whitespace separating a command's name and body is normalised to a single space
+///   (or removed if the body is empty)
+/// - trim markers are written as `(%- ` and ` -%)`
+///   (keeping line breaks before ` -%)` if re-indenting is enabled)
+/// - optionally, lines of nested bodies are re-indented (see [`FormatOptions::indent`](struct.FormatOptions.html#structfield.indent))
+/// - inside of commands, escapes of `(`, `)`, `%`, `{` and `}` are removed where the character can't form a delimiter
+///   (the engine removes them anyway before a command gets its body), so e.g. `(%lit \{a\(b%)` becomes `(%lit {a(b%)`.
+///   Other escapes, and all escapes outside of commands (which the engine outputs as written), are kept
+/// - commands with nested commands in their name are formatted like other commands,
+///   unless a nested command contains the separator between name and body
+///
+/// # Fails
+/// If the template has unbalanced delimiters or an unterminated comment,
+/// it is not formatted and the issues about these are returned
+pub fn format_template(src: &str, options: &FormatOptions) -> Result<String, Vec<Issue>> {
+    let tree = SyntaxTree::parse(src);
+    if !tree.issues.is_empty() {
+        return Err(tree.issues);
+    }
+    let mut f = Formatter {
+        src,
+        options,
+        res: String::with_capacity(src.len()),
+    };
+    f.nodes(&tree.nodes, 0, src.len(), 0);
+    Ok(f.res)
+}
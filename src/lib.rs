@@ -12,17 +12,55 @@
 //! - otherwise, it is kept as `\:`
 //!
 //! Command names may not contain whitespace characters or any of the characters `%(){}`
+//!
+//! Comments start with `(%#` and end with the next `%)` that is not escaped, i.e. they don't nest
+//! - they can appear anywhere, also inside the body of a command, and produce no output
+//! - they may contain unbalanced `(%`
+//!
+//! Whitespace around a command can be removed with trim markers:
+//! - `(%- cmd%)` removes the whitespace (including line breaks) before the command
+//! - `(%cmd -%)` removes the whitespace after the command
+//! - the markers need to be separated from the rest of the command by whitespace,
+//!   so that e.g. `(%let x=-%)` is not affected
+//! - only the text of the template is trimmed, never the output of another command
 
+pub use crate::batch::RenderJob;
+pub use crate::clock::Clock;
+pub use crate::data::{CsvOptions, DataError, DataFormat, DataValue};
+pub use crate::dry_run::{DryRun, SideEffect};
+pub use crate::escape::Escaper;
+pub use crate::format::{format_template, FormatOptions};
+pub use crate::predefined_commands::CommandSet;
+pub use crate::process_cache::{ProcessCache, ProcessKey};
+pub use crate::sandbox::Sandbox;
+pub use crate::syntax::{Binding, CommandNode, SyntaxNode, SyntaxTree};
+pub use crate::tree::{TreeFile, TreeOptions, TreeReport};
 pub use crate::util::{RowCol, Span};
-use std::collections::{HashMap, HashSet};
+pub use crate::watch::{FileWatcher, TreeWatcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::marker::PhantomData;
 use std::mem::{replace, take};
 use std::path::PathBuf;
+use std::sync::Arc;
 use tlib::iter_tools::{AutoEscape, IterSplit, Unescape};
+use trim::OutputPiece;
 
+mod batch;
+mod clock;
+mod data;
+mod dry_run;
+mod escape;
+mod format;
+mod lint;
+mod process_cache;
+mod sandbox;
 mod shell_util;
+mod syntax;
+mod tree;
+mod trim;
 mod util;
+mod watch;
 
 // fixme: for some reason the subspan calculations are slightly off
 
@@ -34,6 +72,8 @@ pub mod predefined_commands;
 /// ppm has a philosophy of always allowing you to have an end result (be it empty),
 /// thus there are no real "errors" in the sense that they stop execution.
 /// This means that all issues are handled as warnings , even if they are errors
+///
+/// Still, issues have a [`severity`](#method.severity) that says if the result is likely to be wrong
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Issue {
     /// An identifier for the issue. It is not unique, an example is `command:missing_args`
@@ -44,6 +84,15 @@ pub struct Issue {
     pub span: Span,
 }
 
+/// How severe an [`Issue`](struct.Issue.html) is
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+    /// The command still did what it was supposed to, but something may be off
+    Warning,
+    /// The command could not do (all of) what it was supposed to
+    Error,
+}
+
 /// A helper struct for displaying [`Issue`](struct.Issue.html)s
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct IssueDisplay<'a> {
@@ -76,6 +125,19 @@ impl Issue {
         }
     }
 
+    /// The severity of the issue, which is derived from its id
+    ///
+    /// Issues are warnings if their id ends with `:partial` or has a part (separated by colons) `warning`,
+    /// all other issues are errors
+    #[inline]
+    pub fn severity(&self) -> Severity {
+        if self.id.ends_with(":partial") || self.id.split(':').any(|s| s == "warning") {
+            Severity::Warning
+        } else {
+            Severity::Error
+        }
+    }
+
     /// Creates a value that can be formatted by [`fmt::Display`](https://doc.rust-lang.org/std/fmt/trait.Display.html)
     #[inline]
     pub fn display(&self, original_src: &str) -> IssueDisplay {
@@ -93,7 +155,7 @@ impl Issue {
 fn absorb_new_issues(issues: &mut Vec<Issue>, subspan: Span, new_issues: Vec<Issue>) {
     issues.extend(new_issues.into_iter().map(|mut e| {
         e.span.start += subspan.start;
-        debug_assert!(e.span.end() < subspan.end(), "malformed subspan");
+        debug_assert!(e.span.end() <= subspan.end(), "malformed subspan");
         e
     }));
 }
@@ -134,6 +196,40 @@ impl<'a, 'b> CommandConfig<'a, 'b> {
         }
     }
 
+    /// Creates an issue with id `"sandbox:forbidden"` and span `self.cmd_span`
+    #[inline]
+    pub fn forbidden(&self, what: &str) -> Issue {
+        Issue {
+            id: "sandbox:forbidden",
+            msg: format!("the sandbox does not allow {}", what),
+            span: self.cmd_span,
+        }
+    }
+
+    /// Records that the command read a file or directory, see [`Engine::accessed_files`](struct.Engine.html#method.accessed_files)
+    ///
+    /// This should also be done if reading failed, since the file may appear later
+    #[inline]
+    pub fn record_access(&mut self, path: PathBuf) {
+        self.engine.accessed_files.insert(path);
+    }
+
+    /// In a dry run (see [`Engine::with_dry_run`](struct.Engine.html#method.with_dry_run)),
+    /// records a side effect in the audit log and returns the placeholder to output instead of performing it
+    ///
+    /// Returns `None` if this is no dry run, i.e. the command should perform the side effect
+    #[inline]
+    pub fn dry_run(&mut self, effect: SideEffect) -> Option<String> {
+        let placeholder = self
+            .engine
+            .dry_run
+            .as_ref()?
+            .placeholder(&effect)
+            .to_string();
+        self.engine.audit_log.push(effect);
+        Some(placeholder)
+    }
+
     /// Pushes an issue with id `"command:missing_args"` and span `self.cmd_span` onto `self.issues`
     #[inline]
     pub fn push_missing_args(&mut self, msg: &str) {
@@ -181,8 +277,112 @@ impl<'a, 'b> CommandConfig<'a, 'b> {
         absorb_new_issues(self.issues, span, is);
         Some(res)
     }
+
+    /// Like [`self.process_subbody`](#method.process_subbody), but pushes the issues onto `issues` instead of `self.issues`
+    #[inline]
+    pub fn process_subbody_into(
+        &mut self,
+        subbody: String,
+        subspan: Span,
+        issues: &mut Vec<Issue>,
+    ) -> Option<String> {
+        let eng: &mut Engine<Free> = self.free_engine();
+        let (res, is) = eng.process_new(subbody);
+        let span = subspan.relative_to(&self.body_span)?;
+        absorb_new_issues(issues, span, is);
+        Some(res)
+    }
+
+    /// Processes one iteration of a loop body (like [`self.process`](#method.process)),
+    /// returning its output and the signal that ended it early, if any
+    ///
+    /// Processing stops at the first [`LoopControl`](enum.LoopControl.html) signal,
+    /// so the output only contains what was processed up to that point
+    pub fn process_loop_body(&mut self, s: String) -> (String, Option<LoopControl>) {
+        self.engine.loop_depth += 1;
+        let res = self.process(s);
+        self.engine.loop_depth -= 1;
+        (res, self.engine.loop_control.take())
+    }
+
+    /// Runs `f` with the engine's auto-escaper (see [`Engine::with_auto_escape`](struct.Engine.html#method.with_auto_escape)) disabled
+    ///
+    /// Use this to process text that is stored in variables, since it is escaped when it is substituted,
+    /// and text that is used as data (like names, paths and patterns) instead of being output
+    pub fn without_auto_escape<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let escaper = self.engine.auto_escape.take();
+        let res = f(self);
+        self.engine.auto_escape = escaper;
+        res
+    }
+
+    /// Escapes `s` with the engine's auto-escaper (see [`Engine::with_auto_escape`](struct.Engine.html#method.with_auto_escape)),
+    /// if it has one
+    ///
+    /// Use this for output that is taken from text processed with [`without_auto_escape`](#method.without_auto_escape)
+    pub fn auto_escape(&self, s: String) -> String {
+        match self.engine.auto_escape {
+            Some(e) => e.escape(&s),
+            None => s,
+        }
+    }
+
+    /// Sends a signal to the innermost enclosing loop,
+    /// which makes everything up to that loop's body stop processing
+    ///
+    /// Returns `false` (and does nothing) if there is no enclosing loop
+    #[inline]
+    pub fn signal_loop(&mut self, signal: LoopControl) -> bool {
+        if self.engine.loop_depth == 0 {
+            return false;
+        }
+        self.engine.loop_control = Some(signal);
+        true
+    }
+
+    /// The maximum number of iterations of `while` loops
+    #[inline]
+    pub fn loop_limit(&self) -> usize {
+        self.engine.loop_limit.unwrap_or(DEFAULT_LOOP_LIMIT)
+    }
+
+    /// Counts an iteration of a `while` loop or a `for` loop over a range against the number of iterations
+    /// that all loops may make together, if the engine limits that
+    ///
+    /// Returns `false` if no iterations are left, in which case the loop should end without an issue
+    pub(crate) fn spend_iteration(&mut self) -> bool {
+        match &mut self.engine.loop_budget {
+            Some(0) => false,
+            Some(n) => {
+                *n -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// The maximum number of iterations of `for` loops over ranges,
+    /// only set if the engine's loop limit was set explicitly
+    #[inline]
+    pub fn range_limit(&self) -> Option<usize> {
+        self.engine.loop_limit
+    }
+}
+
+/// A signal that ends the current iteration of a loop early
+///
+/// See [`CommandConfig::signal_loop`](struct.CommandConfig.html#method.signal_loop)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum LoopControl {
+    /// Ends the loop
+    Break,
+    /// Continues with the next iteration of the loop
+    Continue,
 }
 
+/// The number of iterations after which `while` loops are aborted by default
+pub const DEFAULT_LOOP_LIMIT: usize = 10_000;
+
 /// The type of a command handler function
 pub type CommandHandler = fn(CommandConfig) -> String;
 
@@ -195,22 +395,89 @@ pub enum Captured {}
 ///
 /// The type parameter is a typestate with either [`Free`](enum.Free.html) or [`Captured`](enum.Captured.html).\
 /// An `Engine<Captured>` cannot be created by you, the user. It is, however, part of [`CommandConfig`](struct.CommandConfig.html).
-#[derive(Clone)]
 pub struct Engine<State> {
     /// The variables stored in the engine
     pub vars: HashMap<String, String>,
     root_path: Option<PathBuf>,
-    commands: HashMap<String, CommandHandler>,
+    commands: Arc<HashMap<String, CommandHandler>>,
+    process_cache: Option<ProcessCache>,
+    /// The process cache of the engine a batch job is rendered for, see [`process_batch`](#method.process_batch)
+    shared_process_cache: Option<Arc<ProcessCache>>,
+    sandbox: Sandbox,
+    clock: Clock,
+    #[cfg(feature = "regex")]
+    regex_cache: HashMap<String, regex::Regex>,
+    loop_control: Option<LoopControl>,
+    loop_depth: usize,
+    loop_limit: Option<usize>,
+    /// The number of iterations all loops may still make together, only limited by [`analyze`](#method.analyze)
+    loop_budget: Option<usize>,
+    auto_escape: Option<Escaper>,
+    trim_command_lines: bool,
+    accessed_files: BTreeSet<PathBuf>,
+    dry_run: Option<DryRun>,
+    audit_log: Vec<SideEffect>,
     _marker: PhantomData<State>,
 }
 
+// not derived because that would require `State: Clone`
+impl<State> Clone for Engine<State> {
+    #[inline]
+    fn clone(&self) -> Self {
+        self.clone_with_process_cache(self.process_cache.clone())
+    }
+}
+
+impl<State> Engine<State> {
+    /// Clones everything but the process cache, which is replaced with `process_cache`
+    fn clone_with_process_cache(&self, process_cache: Option<ProcessCache>) -> Self {
+        Self {
+            vars: self.vars.clone(),
+            root_path: self.root_path.clone(),
+            commands: Arc::clone(&self.commands),
+            process_cache,
+            shared_process_cache: self.shared_process_cache.clone(),
+            sandbox: self.sandbox,
+            clock: self.clock,
+            #[cfg(feature = "regex")]
+            regex_cache: self.regex_cache.clone(),
+            loop_control: self.loop_control,
+            loop_depth: self.loop_depth,
+            loop_limit: self.loop_limit,
+            loop_budget: self.loop_budget,
+            auto_escape: self.auto_escape,
+            trim_command_lines: self.trim_command_lines,
+            accessed_files: self.accessed_files.clone(),
+            dry_run: self.dry_run.clone(),
+            audit_log: self.audit_log.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<State> std::fmt::Debug for Engine<State> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Engine")
-            .field("vars", &self.vars)
+        let mut d = f.debug_struct("Engine");
+        d.field("vars", &self.vars)
             .field("root_path", &self.root_path)
             .field("commands", &self.commands.keys().collect::<HashSet<_>>())
-            .finish()
+            .field("process_cache", &self.process_cache)
+            .field("shared_process_cache", &self.shared_process_cache)
+            .field("sandbox", &self.sandbox)
+            .field("clock", &self.clock)
+            .field("loop_limit", &self.loop_limit)
+            .field("loop_budget", &self.loop_budget)
+            .field("auto_escape", &self.auto_escape)
+            .field("trim_command_lines", &self.trim_command_lines)
+            .field("accessed_files", &self.accessed_files)
+            .field("dry_run", &self.dry_run)
+            .field("audit_log", &self.audit_log);
+        #[cfg(feature = "regex")]
+        d.field(
+            "regex_cache",
+            &self.regex_cache.keys().collect::<HashSet<_>>(),
+        );
+        d.finish()
     }
 }
 
@@ -227,7 +494,22 @@ impl Engine<Free> {
             root_path: None,
             // basic_commands: HashMap::new(),
             // block_commands: HashMap::new(),
-            commands: HashMap::new(),
+            commands: Arc::new(HashMap::new()),
+            process_cache: None,
+            shared_process_cache: None,
+            sandbox: Sandbox::default(),
+            clock: Clock::default(),
+            #[cfg(feature = "regex")]
+            regex_cache: HashMap::new(),
+            loop_control: None,
+            loop_depth: 0,
+            loop_limit: None,
+            loop_budget: None,
+            auto_escape: None,
+            trim_command_lines: false,
+            accessed_files: BTreeSet::new(),
+            dry_run: None,
+            audit_log: vec![],
             _marker: PhantomData,
         }
     }
@@ -259,6 +541,180 @@ impl Engine<Free> {
         self
     }
 
+    /// Adds all environment variables whose names start with `prefix` to the engine's variables
+    ///
+    /// Variables that are already set are overwritten.
+    /// Environment variables that are not valid unicode are skipped
+    ///
+    /// Does nothing if the [sandbox](#method.with_sandbox) does not allow reading environment variables,
+    /// so the sandbox has to be set before calling this
+    #[inline]
+    pub fn with_env_vars(mut self, prefix: &str) -> Self {
+        if !self.sandbox.allow_env {
+            return self;
+        }
+        self.vars.extend(std::env::vars_os().filter_map(|(k, v)| {
+            match (k.into_string(), v.into_string()) {
+                (Ok(k), Ok(v)) if k.starts_with(prefix) => Some((k, v)),
+                _ => None,
+            }
+        }));
+        self
+    }
+
+    /// Sets the sandbox that restricts what commands may access
+    #[inline]
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// The sandbox that restricts what commands may access
+    #[inline]
+    pub fn sandbox(&self) -> Sandbox {
+        self.sandbox
+    }
+
+    /// Sets the clock that commands use to get the current time
+    #[inline]
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the number of iterations after which `while` loops and `for` loops over ranges are aborted
+    ///
+    /// Without it, `while` loops are aborted after [`DEFAULT_LOOP_LIMIT`](constant.DEFAULT_LOOP_LIMIT.html) iterations
+    /// and ranges aren't limited
+    #[inline]
+    pub fn with_loop_limit(mut self, limit: usize) -> Self {
+        self.loop_limit = Some(limit);
+        self
+    }
+
+    /// Escapes the value of every variable substitution (`(%name%)` or `(%var name%)`) with `escaper`
+    ///
+    /// Use `(%raw name%)` or `(%safe name%)` to substitute a variable without escaping it.
+    /// Other output, like that of `run`, is not escaped.
+    ///
+    /// Text that ends up in variables (the value of `let`, the head of `for` and the text of `re_captures`)
+    /// and text that commands use as data (like variable names, paths, patterns, conditions and the arguments of `run`)
+    /// is processed without escaping.
+    /// Commands that output parts of such text (like `alt`, `sort` and the regex commands) escape their output instead
+    #[inline]
+    pub fn with_auto_escape(mut self, escaper: Escaper) -> Self {
+        self.auto_escape = Some(escaper);
+        self
+    }
+
+    /// Disables escaping variable substitutions
+    #[inline]
+    pub fn without_auto_escape(mut self) -> Self {
+        self.auto_escape = None;
+        self
+    }
+
+    /// Removes lines that only contain commands without output (like `(%let x=1%)`) and whitespace,
+    /// including their line break
+    ///
+    /// This also applies to the bodies of commands like `for`, since they are processed the same way
+    #[inline]
+    pub fn with_trimmed_command_lines(mut self) -> Self {
+        self.trim_command_lines = true;
+        self
+    }
+
+    /// Keeps lines that only contain commands without output (the default)
+    #[inline]
+    pub fn without_trimmed_command_lines(mut self) -> Self {
+        self.trim_command_lines = false;
+        self
+    }
+
+    /// The (absolute) paths of all files and directories that were read while processing,
+    /// including data files loaded with [`load_data`](#method.load_data)
+    ///
+    /// This is what needs to be watched to know when a template has to be re-rendered
+    #[inline]
+    pub fn accessed_files(&self) -> &BTreeSet<PathBuf> {
+        &self.accessed_files
+    }
+
+    /// Returns and forgets the paths of all files and directories that were read while processing
+    #[inline]
+    pub fn take_accessed_files(&mut self) -> BTreeSet<PathBuf> {
+        take(&mut self.accessed_files)
+    }
+
+    /// Makes commands record their side effects instead of performing them
+    ///
+    /// `run`, `include`, `lsdir` and the commands that read data files then don't touch the
+    /// file system or spawn processes, but append what they would do to the
+    /// [`audit_log`](#method.audit_log) and output the placeholders of `dry_run` instead.
+    /// [`process_tree`](#method.process_tree) doesn't write, copy or create anything either.
+    /// The sandbox still applies, so forbidden side effects are reported as usual and not recorded
+    #[inline]
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Makes commands perform their side effects (the default)
+    #[inline]
+    pub fn without_dry_run(mut self) -> Self {
+        self.dry_run = None;
+        self
+    }
+
+    /// The placeholders of the dry run, or `None` if side effects are performed
+    #[inline]
+    pub fn dry_run(&self) -> Option<&DryRun> {
+        self.dry_run.as_ref()
+    }
+
+    /// The side effects that were recorded instead of performed in a dry run, in order
+    #[inline]
+    pub fn audit_log(&self) -> &[SideEffect] {
+        &self.audit_log
+    }
+
+    /// Returns and forgets the side effects that were recorded in a dry run
+    #[inline]
+    pub fn take_audit_log(&mut self) -> Vec<SideEffect> {
+        take(&mut self.audit_log)
+    }
+
+    /// Enables caching the output of processes spawned by the `run` command
+    ///
+    /// Repeated invocations with the same program, arguments, working directory and environment
+    /// are then served from the cache instead of spawning a new process
+    #[inline]
+    pub fn with_process_cache(mut self, cache: ProcessCache) -> Self {
+        self.process_cache = Some(cache);
+        self
+    }
+
+    /// Disables caching the output of processes
+    #[inline]
+    pub fn without_process_cache(mut self) -> Self {
+        self.process_cache = None;
+        self
+    }
+
+    /// The process cache, if one is enabled
+    ///
+    /// Use this to persist the cache once rendering is done (see [`ProcessCache::save`](struct.ProcessCache.html#method.save))
+    #[inline]
+    pub fn process_cache(&self) -> Option<&ProcessCache> {
+        self.process_cache.as_ref()
+    }
+
+    /// Removes the process cache from the engine, returning it
+    #[inline]
+    pub fn take_process_cache(&mut self) -> Option<ProcessCache> {
+        self.process_cache.take()
+    }
+
     /// Tests if a character may not appear inside a command name
     ///
     /// Invalid characters are whitespace or any of `%(){}`
@@ -279,7 +735,7 @@ impl Engine<Free> {
         handler: CommandHandler,
     ) -> Result<Option<CommandHandler>, InvalidCommandName> {
         if Self::is_valid_command_name(cmd) {
-            Ok(self.commands.insert(cmd.to_string(), handler))
+            Ok(Arc::make_mut(&mut self.commands).insert(cmd.to_string(), handler))
         } else {
             Err(InvalidCommandName(cmd.to_string()))
         }
@@ -303,12 +759,53 @@ impl Engine<Free> {
             Err(errs)
         }
     }
+
+    /// The names of all commands the engine knows, sorted
+    #[inline]
+    pub fn command_names(&self) -> Vec<&str> {
+        let mut res = self.commands.keys().map(|k| &k[..]).collect::<Vec<_>>();
+        res.sort_unstable();
+        res
+    }
+
+    /// Counts the commands and comments that are opened in `s` but not closed,
+    /// e.g. to tell if an interactive input needs more lines
+    ///
+    /// Escaped delimiters are ignored, like when processing, and so is an unmatched `%)`
+    pub fn open_commands(s: &str) -> usize {
+        let mut lvl = 0usize;
+        let mut iter = s.char_indices().auto_escape(|&(_, c)| c == '\\').peekable();
+        while let Some((esc, (i, c))) = iter.next() {
+            match c {
+                '(' if !esc && s[i..].starts_with("(%#") => match util::comment_len(&s[i..]) {
+                    Some(len) => while iter.next_if(|&(_, (j, _))| j < i + len).is_some() {},
+                    None => return lvl + 1,
+                },
+                '(' if !esc => {
+                    if let Some(&(false, (_, '%'))) = iter.peek() {
+                        iter.next();
+                        lvl += 1;
+                    }
+                }
+                '%' if !esc => {
+                    if let Some(&(false, (_, ')'))) = iter.peek() {
+                        iter.next();
+                        lvl = lvl.saturating_sub(1);
+                    }
+                }
+                _ => (),
+            }
+        }
+        lvl
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum SourceAtom {
     Str(String),
     Command(String),
+    /// A comment, with the length it had in the source
+    Comment(usize),
 }
 
 fn parse_commands(mut s: String, issues: &mut Vec<Issue>) -> Vec<SourceAtom> {
@@ -320,6 +817,28 @@ fn parse_commands(mut s: String, issues: &mut Vec<Issue>) -> Vec<SourceAtom> {
         let mut iter = s.char_indices().auto_escape(|&(_, c)| c == '\\').peekable();
         while let Some((esc, (i, c))) = iter.next() {
             match c {
+                '(' if !esc && s[i..].starts_with("(%#") => {
+                    let len = match util::comment_len(&s[i..]) {
+                        Some(len) => len,
+                        None => {
+                            issues.push(Issue {
+                                id: "comment:no_end",
+                                msg: "Comment has no end".to_string(),
+                                span: Span::new(orig_s_len - s_len + i, s_len - i),
+                            });
+                            s.len() - i
+                        }
+                    };
+                    if lvl == 0 {
+                        let rest = s.split_off(i + len);
+                        s.truncate(i);
+                        res.push(SourceAtom::Str(replace(&mut s, rest)));
+                        res.push(SourceAtom::Comment(len));
+                        continue 'outer;
+                    }
+                    // inside a command, the comment is left for when the body is processed
+                    while iter.next_if(|&(_, (j, _))| j < i + len).is_some() {}
+                }
                 '(' if !esc => {
                     if let Some(&(false, (_, '%'))) = iter.peek() {
                         lvl += 1;
@@ -385,6 +904,21 @@ impl Engine<Free> {
 
         let mut start = 0;
         for atom in parsed {
+            // a loop body was ended early, the rest is skipped until the loop is reached
+            if self.loop_control.is_some() {
+                break;
+            }
+            if let SourceAtom::Comment(len) = atom {
+                // comments behave like commands without output
+                res.push(OutputPiece {
+                    text: String::new(),
+                    is_command: true,
+                    trim_before: false,
+                    trim_after: false,
+                });
+                start += len;
+                continue;
+            }
             let is_command = matches!(atom, SourceAtom::Command(_));
             if is_command {
                 start += 2;
@@ -394,11 +928,14 @@ impl Engine<Free> {
             match atom {
                 SourceAtom::Str(s) => {
                     s_len = s.len();
-                    res.push(s);
+                    res.push(OutputPiece::text(s));
                 }
+                SourceAtom::Comment(_) => unreachable!(),
                 SourceAtom::Command(s) => {
                     s_len = s.len();
-                    let mut spl = s
+                    let (lead, trail) = trim::find_markers(&s);
+                    let start = start + lead;
+                    let mut spl = s[lead..s.len() - trail]
                         .char_indices()
                         .auto_escape(|&(_, c)| c == '\\')
                         .splitn(2, |&(esc, (_, c))| !esc && c.is_whitespace(), true)
@@ -421,11 +958,15 @@ impl Engine<Free> {
                             (s, orig_len)
                         });
 
+                    let mut output = String::new();
                     let (mut cmd, orig_cmd_len) = spl.next().unwrap();
                     let sep_len = spl.next().map_or(0, |t: (String, usize)| t.1);
                     let (mut body, orig_body_len) = spl.next().unwrap_or((String::new(), 0));
 
-                    let cmd_span = Span::new(start - 2, orig_cmd_len + sep_len + orig_body_len + 4);
+                    let cmd_span = Span::new(
+                        start - lead - 2,
+                        lead + orig_cmd_len + sep_len + orig_body_len + trail + 4,
+                    );
                     let mut body_span = Span::new(start + orig_cmd_len, orig_body_len);
 
                     match self
@@ -453,20 +994,26 @@ impl Engine<Free> {
                                 issues,
                                 engine: self.capture(),
                             };
-                            res.push(handler(cfg));
+                            output = handler(cfg);
                         }
                         None => {
                             issues.push(Issue {
                                 id: "command:unknown",
                                 msg: format!(
                                     "invalid or unknown command at {} (starting with `(%{}`)",
-                                    RowCol::from_index(start - 2, &orig_s),
+                                    RowCol::from_index(cmd_span.start, &orig_s),
                                     if s.len() < 10 { &s } else { &s[..10] }
                                 ),
                                 span: cmd_span,
                             });
                         }
                     }
+                    res.push(OutputPiece {
+                        text: output,
+                        is_command: true,
+                        trim_before: lead > 0,
+                        trim_after: trail > 0,
+                    });
                 }
             }
 
@@ -476,7 +1023,11 @@ impl Engine<Free> {
             }
         }
 
-        res.join("")
+        trim::apply_markers(&mut res);
+        if self.trim_command_lines {
+            trim::remove_command_lines(&mut res);
+        }
+        res.into_iter().map(|p| p.text).collect()
     }
 
     /// Creates a new `Vec` to hold issues, before calling [`self.process`](#method.process)
@@ -495,6 +1046,14 @@ impl Engine<Free> {
 mod tests {
     use super::*;
 
+    /// An engine with the predefined commands and `include`, which isn't one of them
+    fn engine_with_include(vars: HashMap<String, String>) -> Engine<Free> {
+        let mut en = Engine::with_predefined_commands(vars);
+        en.add_command("include", predefined_commands::include_handler)
+            .unwrap();
+        en
+    }
+
     #[test]
     fn test_vars() {
         let s = "abc(%var1%)def(%var2%)ghi";
@@ -517,6 +1076,63 @@ mod tests {
         assert_eq!(&s, "test 123456 789\n");
     }
 
+    #[test]
+    fn test_run_cached() {
+        let key = ProcessKey::new(
+            "echo".to_string(),
+            vec!["abc".to_string()],
+            std::env::current_dir().unwrap(),
+        );
+        let mut cache = ProcessCache::new();
+        cache.insert(key, "cached\n".to_string());
+        let mut en = Engine::with_predefined_commands(HashMap::new()).with_process_cache(cache);
+        let (s, i) = en.process_new("(%run echo abc%)(%run echo def%)".to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&s, "cached\ndef\n");
+        assert_eq!(en.process_cache().map(ProcessCache::len), Some(2));
+    }
+
+    #[test]
+    fn test_env() {
+        // the environment is shared with the tests that run in parallel, so it is only read
+        let path = std::env::var("PATH").unwrap();
+        let s = "(%env PATH%)(%env PPM_TEST_UNSET:fallback%)";
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(res, format!("{}fallback", path));
+
+        let mut en =
+            Engine::with_predefined_commands(HashMap::new()).with_sandbox(Sandbox::strict());
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i.len(), 2);
+        assert!(i.iter().all(|i| i.id == "sandbox:forbidden"));
+        assert_eq!(&res, "fallback");
+
+        let en = Engine::new(HashMap::new()).with_env_vars("PAT");
+        assert_eq!(en.vars.get("PATH"), Some(&path));
+        let en = Engine::new(HashMap::new())
+            .with_sandbox(Sandbox::strict())
+            .with_env_vars("PAT");
+        assert_eq!(en.vars.get("PATH"), None);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_date() {
+        // 2020-02-29 12:34:56 UTC
+        let mut en = Engine::with_predefined_commands(HashMap::new())
+            .with_clock(Clock::fixed_unix(1582979696));
+        let s = "(%date %Y-%m-%d %H\\:%M%)|(%date %Y-%m-%d:add 1y:sub 1d%)|(%date %d.%m.%Y:parse 2000-01-02T03\\:04\\:05Z%)|(%date %s:parse 01/02/2003:parse_format %d/%m/%Y%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "2020-02-29 12:34|2021-02-27|02.01.2000|1044057600");
+
+        let (res, i) = en.process_new("(%date %Y:add 3 parsecs%)".to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(&res, "");
+    }
+
     #[test]
     fn test_alt() {
         let s = "(%alt :::a%)(%alt ::a:b:c%)";
@@ -527,6 +1143,389 @@ mod tests {
         assert_eq!(&s, "aa");
     }
 
+    #[test]
+    fn test_match() {
+        let mut vars = HashMap::new();
+        vars.insert("os".to_string(), "debian".to_string());
+        let mut en = Engine::with_predefined_commands(vars);
+        let s = "(%match (%os%): arch => pacman : fedora => (%run false%) : deb* => apt : _ => ?%)|(%match x\\*: x* => glob : x\\* => lit%)|(%match c: a => 1 : b => 2%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].id, "command:match:no_arm");
+        assert_eq!(&res, "apt|glob|");
+        #[cfg(feature = "regex")]
+        {
+            let s = "(%match v1.2: /^v[0-9]+\\.[0-9]+$/ => version : _ => other%)";
+            let (res, i) = en.process_new(s.to_string());
+            assert_eq!(i, vec![]);
+            assert_eq!(&res, "version");
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        let mut vars = HashMap::new();
+        vars.insert("x".to_string(), "<a href='#'>".to_string());
+        let mut en = Engine::with_predefined_commands(vars.clone());
+        let s = "(%html (%x%)%)|(%url a b/c%)|(%shell_quote it's%)|(%json_str say \"hi\"%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(
+            &res,
+            "&lt;a href=&#39;#&#39;&gt;|a%20b%2Fc|'it'\\''s'|say \\\"hi\\\""
+        );
+
+        let mut en = Engine::with_predefined_commands(vars).with_auto_escape(Escaper::Xml);
+        let (res, i) = en.process_new("<p>(%x%)(%var x%)(%raw x%)</p>".to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(
+            &res,
+            "<p>&lt;a href=&apos;#&apos;&gt;&lt;a href=&apos;#&apos;&gt;<a href='#'></p>"
+        );
+
+        let mut en = en.with_auto_escape(Escaper::Html);
+        en.vars.insert("x".to_string(), "a<b".to_string());
+        let s = "(%let y=(%x%)%)(%y%)|(%for i in (%x%)\\:c:[(%i%)]%)|(%raw y%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "a&lt;b|[a&lt;b][c]|a<b");
+    }
+
+    #[test]
+    fn test_auto_escape_arguments() {
+        let dir = std::env::temp_dir().join(format!("ppm-escape-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a&b.txt"), "included").unwrap();
+        let mut vars = HashMap::new();
+        for (k, v) in &[("x", "a<b"), ("os", "a&b"), ("q", "a'b")] {
+            vars.insert(k.to_string(), v.to_string());
+        }
+        vars.insert("dir".to_string(), dir.to_str().unwrap().to_string());
+
+        // arguments that are used as data are not escaped, so escaping commands don't escape twice
+        let mut en = engine_with_include(vars).with_auto_escape(Escaper::Html);
+        let s = "(%html (%x%)%)|(%url (%x%)%)|(%match (%os%): a&b => hit : _ => miss%)|(%include (%dir%)/(%os%).txt%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "a&lt;b|a%3Cb|hit|included");
+
+        // output taken from such arguments is escaped instead
+        let s = "(%alt :(%x%)%)|(%sort +:(%q%):(%os%)%)|(%sort_by v + (%v%):(%q%):(%os%)%)|(%env PPM_TEST_UNSET:(%x%)%)|(%run echo (%x%)%)(%warn (%x%)%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(&i[0].msg, "a<b");
+        assert_eq!(&res, "a&lt;b|a&amp;b:a&#39;b|a&amp;b:a&#39;b|a&lt;b|a<b\n");
+
+        #[cfg(feature = "regex")]
+        {
+            let s = "(%re_match ^a&b$:(%os%)%)|(%re_find <.:(%x%)%)|(%re_sub &:+:(%os%)%)";
+            let (res, i) = en.process_new(s.to_string());
+            assert_eq!(i, vec![]);
+            assert_eq!(&res, "true|&lt;b|a+b");
+        }
+
+        let mut en = en.with_auto_escape(Escaper::Url);
+        let (res, i) = en.process_new("(%include (%dir%)/(%os%).txt%)".to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "included");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        let s = "a  \n (%- let x=1 -%)\n  b(%let y=- -%) c(%x%)(%y%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "abc1-");
+
+        let (_, i) = en.process_new("(%- unknown -%)".to_string());
+        assert_eq!(i[0].span, Span::new(4, 7));
+
+        let mut en = en.with_trimmed_command_lines();
+        let s = "(%let x=1%)\nstart\n  (%let y=2%) (%let z=3%)\n(%for i in a\\:b:\n  (%let w=(%i%)%)\n- (%w%)\n%)\nx (%let v=1%)\nend (%x%)(%y%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        // the line breaks right after `:` and `%)` of `for` are kept, since it has output
+        assert_eq!(&res, "start\n\n- a\n\n- b\n\nx \nend 12");
+    }
+
+    #[test]
+    fn test_comment() {
+        let mut vars = HashMap::new();
+        vars.insert("x".to_string(), "1".to_string());
+        let mut en = Engine::with_predefined_commands(vars);
+        let s = "a(%# (% unbalanced: \\%) %)b(%for i in (%# : %)c\\:d:(%i%)(%# %)%)(%unknown%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(&res, "abcd");
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].span, Span::new(66, 7));
+
+        let mut en = en.with_trimmed_command_lines();
+        let (res, i) = en.process_new("a\n  (%# note %)\nb(%# open".to_string());
+        assert_eq!(&res, "a\nb");
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].id, "comment:no_end");
+    }
+
+    #[test]
+    fn test_try() {
+        let mut vars = HashMap::new();
+        vars.insert("error.id".to_string(), "outer".to_string());
+        let mut en = engine_with_include(vars)
+            .with_root_path(std::env::temp_dir().join("ppm-does-not-exist"));
+        let s = "(%try (%include missing.txt%):<(%error.id%)/(%error.count%)>%)|(%try ok(%sort +/shuffle:a%):unused%)|(%try a(%for i in x:(%i%):frobnicate%)%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(&res, "<io_error/1>|unused|ax");
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].severity(), Severity::Warning);
+        assert_eq!(en.vars.get("error.id").map(|s| &s[..]), Some("outer"));
+
+        let (res, i) = en.process_new("(%try (%x%)%)(%y%)".to_string());
+        assert_eq!(&res, "");
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].span, Span::new(15, 1));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut vars = HashMap::new();
+        vars.insert("title".to_string(), String::new());
+        let mut en = Engine::with_predefined_commands(vars);
+        let s = "a(%warn deprecated: use (%lit b%) instead%)(%error oops%)(%assert (%title%):the page needs a title%)(%assert 1:unused%)(%assert false%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(&res, "a");
+        let ids = i.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                "template:warning",
+                "template:error",
+                "template:assert",
+                "template:assert"
+            ]
+        );
+        assert_eq!(&i[0].msg, "deprecated: use b instead");
+        assert_eq!(i[0].severity(), Severity::Warning);
+        assert_eq!(i[0].span, Span::new(1, 42));
+        assert_eq!(&i[2].msg, "the page needs a title");
+        assert_eq!(&i[3].msg, "assertion failed: false");
+
+        let (_, i) = en.process_new("(%error bad name:x%)".to_string());
+        assert_eq!(i[0].id, "command:invalid_args");
+
+        en.vars.insert("time".to_string(), "12:30".to_string());
+        let s = "(%warn at (%time%)%)(%error late:it is (%time%): hurry%)";
+        let (_, i) = en.process_new(s.to_string());
+        let ids = i.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["template:warning", "template:error"]);
+        assert_eq!(&i[0].msg, "at 12:30");
+        assert_eq!(&i[1].msg, "late: it is 12:30: hurry");
+
+        // the name doesn't change the id, so it can't make an error a warning
+        let (_, i) = en.process_new("(%error warning:boom%)".to_string());
+        assert_eq!(i[0].id, "template:error");
+        assert_eq!(i[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn test_command_sets() {
+        let all = predefined_commands::get_all_handlers();
+        for name in all.keys() {
+            let n = CommandSet::ALL
+                .iter()
+                .filter(|s| s.commands().contains(&&name[..]))
+                .count();
+            assert_eq!(n, 1, "`{}` is in {} command sets", name, n);
+        }
+        // `include` is only enabled explicitly
+        let handlers = predefined_commands::get_handlers(&CommandSet::ALL);
+        assert_eq!(handlers.len(), all.len() + 1);
+        assert!(!all.contains_key("include") && handlers.contains_key("include"));
+        let mut en = Engine::new(HashMap::new());
+        en.add_commands(predefined_commands::get_handlers(&[CommandSet::Core]))
+            .unwrap();
+        let (res, i) = en.process_new("(%let x=1%)(%x%)(%run echo%)".to_string());
+        assert_eq!(&res, "1");
+        assert_eq!(i[0].id, "command:unknown");
+    }
+
+    #[test]
+    fn test_open_commands() {
+        assert_eq!(Engine::open_commands("a (%b%) c"), 0);
+        assert_eq!(Engine::open_commands("(%for x in 1 2:(%x"), 2);
+        assert_eq!(Engine::open_commands("(%lit \\(%%)"), 0);
+        assert_eq!(Engine::open_commands("(%# (% %)"), 0);
+        assert_eq!(Engine::open_commands("(%# unterminated"), 1);
+        assert_eq!(Engine::open_commands("%) (%"), 1);
+        let en = Engine::with_predefined_commands(HashMap::new());
+        let names = en.command_names();
+        assert!(names.windows(2).all(|w| w[0] < w[1]));
+        assert!(names.contains(&"lsdir") && !names.contains(&"include"));
+    }
+
+    #[test]
+    fn test_syntax_tree() {
+        let src =
+            "a (%for x in 1:2:(%-  x%)%) (%# c %)(%let y =(%x%)%) (%lit \\%)%) (%unclosed (%b%)";
+        let tree = SyntaxTree::parse(src);
+        let cmds = tree.commands();
+        let names = cmds.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+        assert_eq!(names, vec!["for", "x", "let", "x", "lit", "unclosed", "b"]);
+        assert_eq!(cmds[0].body_span.index_str(src), "x in 1:2:(%-  x%)");
+        assert_eq!(cmds[1].name_span.index_str(src), "x");
+        assert_eq!(cmds[1].span.index_str(src), "(%-  x%)");
+        assert_eq!(cmds[4].body_span.index_str(src), "\\%)");
+        assert!(!cmds[5].closed && cmds[6].closed);
+        assert!(
+            matches!(tree.nodes[1], SyntaxNode::Comment(span) if span.index_str(src) == "(%# c %)")
+        );
+        assert_eq!(
+            tree.issues.iter().map(|i| i.id).collect::<Vec<_>>(),
+            vec!["command:no_end"]
+        );
+        assert_eq!(tree.command_at(src.find("x%)").unwrap()), Some(cmds[1]));
+        assert_eq!(tree.command_at(1), None);
+
+        let bindings = tree.bindings(src);
+        let bindings = bindings
+            .iter()
+            .map(|b| b.span.index_str(src))
+            .collect::<Vec<_>>();
+        assert_eq!(bindings, vec!["x", "y"]);
+
+        let en = Engine::with_predefined_commands(HashMap::new());
+        let issues = en.analyze("(%lsdir /etc%)(%run rm -rf /%)(%nope%)");
+        assert_eq!(
+            issues.iter().map(|i| i.id).collect::<Vec<_>>(),
+            vec!["command:invalid_args"]
+        );
+        let issues = en.analyze("(%for i from 0 to 99999999999:(%while true:x%)%)");
+        assert_eq!(issues, vec![]);
+        // nested loops don't multiply the number of iterations
+        let issues = en.analyze("(%for i from 1 to 1000:(%for j from 1 to 1000:(%warn x%)%)%)");
+        assert!(!issues.is_empty() && issues.len() <= 1_000);
+        let issues = en
+            .with_loop_limit(10)
+            .analyze("(%for i from 0 to 99999999999:x%)");
+        assert_eq!(issues[0].id, "control_flow:iteration_limit");
+    }
+
+    #[test]
+    fn test_command_info() {
+        use predefined_commands::{command_info, CommandSet, COMMAND_INFO};
+        assert!(COMMAND_INFO.windows(2).all(|w| w[0].name < w[1].name));
+        for set in CommandSet::ALL.iter() {
+            for &name in set.commands() {
+                assert_eq!(command_info(name).map(|i| i.name), Some(name));
+            }
+        }
+        assert_eq!(
+            COMMAND_INFO.len(),
+            CommandSet::ALL
+                .iter()
+                .map(|s| s.commands().len())
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_format_template() {
+        let src = "(%let\tx=a%)(%let y=(%x%)%)\n(%-   for i from 1 to 2:\n      (%-\tmatch (%i%):1 => (%y%):_ => b\n   -%):sep ,  -%)\n  (%lit  (%-  x%)%)(% x%)(%x %) (%# c  %)";
+        let plain = format_template(src, &FormatOptions::default()).unwrap();
+        assert_eq!(
+            plain,
+            "(%let x=a%)(%let y=(%x%)%)\n(%- for i from 1 to 2:\n      (%- match (%i%):1 => (%y%):_ => b -%):sep , -%)\n  (%lit  (%-  x%)%)(% x%)(%x%) (%# c  %)"
+        );
+        let indented = format_template(src, &FormatOptions::default().with_indent("  ")).unwrap();
+        assert_eq!(
+            indented,
+            "(%let x=a%)(%let y=(%x%)%)\n(%- for i from 1 to 2:\n  (%- match (%i%):1 => (%y%):_ => b\n  -%):sep , -%)\n(%lit  (%-  x%)%)(% x%)(%x%) (%# c  %)"
+        );
+        assert_eq!(
+            format_template(&plain, &FormatOptions::default()).unwrap(),
+            plain
+        );
+
+        let render =
+            |s: &str| Engine::with_predefined_commands(HashMap::new()).process_new(s.to_string());
+        let (expected, issues) = render(src);
+        assert_eq!(issues, vec![]);
+        assert_eq!(render(&plain), (expected.clone(), vec![]));
+        assert_eq!(render(&indented), (expected, vec![]));
+
+        let src =
+            "(%let n=lit%)(%let x=a%)(%(%n%) abc%)(%(%n%)  a (%n%)%)(%(%let\tm=x%) b%)(%(%n%)%)";
+        let formatted = format_template(src, &FormatOptions::default()).unwrap();
+        assert_eq!(
+            formatted,
+            "(%let n=lit%)(%let x=a%)(%(%n%) abc%)(%(%n%)  a (%n%)%)(%(%let\tm=x%) b%)(%(%n%)%)"
+        );
+        assert_eq!(render(&formatted), render(src));
+
+        let src = "x\\(y\\{(%let x=a%)(%let z=\\(\\%x\\%\\)%)(%z%)(%lit \\{a\\(b\\%)c\\}%)";
+        let formatted = format_template(src, &FormatOptions::default()).unwrap();
+        assert_eq!(
+            formatted,
+            "x\\(y\\{(%let x=a%)(%let z=(\\%x%\\)%)(%z%)(%lit {a(b\\%)c}%)"
+        );
+        assert_eq!(render(&formatted), render(src));
+        assert_eq!(render(src).0, "x\\(y\\{a{a(b%)c}");
+
+        let issues = format_template("(%a (%b%)", &FormatOptions::default()).unwrap_err();
+        assert_eq!(issues[0].id, "command:no_end");
+    }
+
+    #[test]
+    fn test_lint() {
+        let mut vars = HashMap::new();
+        vars.insert("given".to_string(), "1".to_string());
+        let en = Engine::with_predefined_commands(vars);
+        let src =
+            "(%nope x%)(%missing%)(%given%)(%let y=1%)(%var y%)(%lit (%nope%)%)(%run rm -rf /%)\
+                   (%for i in a\\:b:(%i%)(%loop.index%)%)(%alt (%given%):fallback:never%)\
+                   (%for i frm 1 to 2:x%)(%sort_by x up (%x%):a%)(%while x%)%)";
+        let issues = en.lint(src);
+        let found = issues
+            .iter()
+            .map(|i| (i.id, i.span.index_str(src)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                ("command:unknown", "(%nope x%)"),
+                ("lint:undefined_variable:warning", "missing"),
+                ("lint:unreachable:warning", "never"),
+                ("command:invalid_args", "i frm 1 to 2"),
+                ("command:invalid_args", "x up (%x%)"),
+                ("lint:missing_args:warning", "(%while x%)"),
+                ("command:unmatched_closing_delim", "%)"),
+            ]
+        );
+        assert_eq!(issues[3].msg, "unknown repeat kind: frm");
+        assert_eq!(issues[4].msg, "invalid sorting order: up");
+
+        assert_eq!(en.lint("(%load_ini x.ini%)(%foo%)"), vec![]);
+        let issues =
+            en.lint("(%load_ini x.ini:cfg%)(%cfg.a%)(%foo%)(%csv x.csv:as rows%)(%rows.0%)");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].msg, "variable `foo` is never set");
+        // a computed name may be any variable
+        assert_eq!(en.lint("(%let (%given%)=1%)(%foo%)"), vec![]);
+        assert_eq!(en.lint("(%for (%given%) in a:(%foo%)%)"), vec![]);
+
+        let src = "(%(%given%) x%)(%(%given%)%)";
+        let issues = en.lint(src);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].id, "command:unknown");
+        assert_eq!(issues[0].span.index_str(src), "(%(%given%) x%)");
+        let processed = &en.analyze(src)[0];
+        assert_eq!(
+            (processed.id, processed.span),
+            (issues[0].id, issues[0].span)
+        );
+    }
+
     #[test]
     fn test_lit() {
         let s = "(%lit (%alt abcs%)%)";
@@ -579,6 +1578,82 @@ mod tests {
         let (s, i) = en.process_new(s.to_string());
         assert_eq!(i, vec![]);
         assert_eq!(&s, "12345678910");
+
+        let s = "(%for i from 10 to 1 step 3:(%i%):sep ,%)|(%for i from 0 to 5 step -2:(%i%):sep ,%)|(%for i from 0 to 0 step 0:x%)";
+        let (s, i) = en.process_new(s.to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(&s, "10,7,4,1|0,2,4|");
+        assert_eq!(en.vars.get("i"), None);
+
+        let s = format!(
+            "(%for i from {} to {}:x%)|(%for i from 0 to 18446744073709551616:x%)|(%for i from 0 to 1 step {}:x%)",
+            i128::MIN,
+            i128::MAX,
+            i128::MIN
+        );
+        let (s, i) = en.process_new(s);
+        assert_eq!(i.len(), 3);
+        assert!(i.iter().all(|i| i.id == "command:invalid_args"));
+        assert_eq!(&s, "||");
+
+        let (s, i) = en.process_new(format!("(%for i from 1 to {}:x%)", DEFAULT_LOOP_LIMIT * 2));
+        assert_eq!(i, vec![]);
+        assert_eq!(s.len(), DEFAULT_LOOP_LIMIT * 2);
+
+        let mut en = en.with_loop_limit(3);
+        let (s, i) = en.process_new("(%for i from 1 to 99999999999:(%i%)%)".to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].id, "control_flow:iteration_limit");
+        assert_eq!(&s, "123");
+    }
+
+    #[test]
+    fn test_loop_control() {
+        let mut vars = HashMap::new();
+        for (k, v) in &[
+            ("stop1", ""),
+            ("stop2", "0"),
+            ("stop3", "true"),
+            ("skipb", "1"),
+        ] {
+            vars.insert(k.to_string(), v.to_string());
+        }
+        vars.insert("skipa".to_string(), String::new());
+        vars.insert("skipc".to_string(), String::new());
+        let mut en = Engine::with_predefined_commands(vars).with_loop_limit(3);
+        let s = "(%for i from 1 to 5:(%i%)(%break (%stop(%i%)%)%),%)|(%for i in a\\:b\\:c:(%continue (%skip(%i%)%)%)(%i%)%)|(%for i from 1 to 3:(%eval x(%break%)y%)z%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "1,2,3|ac|x");
+
+        let s = "(%let go=1%)(%while (%go%):(%let go=0%)once%)|(%while true:(%continue%)x%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].id, "control_flow:iteration_limit");
+        assert_eq!(&res, "once|");
+
+        let (res, i) = en.process_new("a(%break%)b".to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].id, "control_flow:outside_loop");
+        assert_eq!(&res, "ab");
+    }
+
+    #[test]
+    fn test_for_loop_vars() {
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        en.vars
+            .insert("loop.index".to_string(), "outer".to_string());
+        let s = "(%for i in a\\:b\\:c:(%loop.index%)/(%loop.length%)[(%loop.first%)|(%loop.last%)](%i%):sep , %)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "0/3[true|]a, 1/3[|]b, 2/3[|true]c");
+        assert_eq!(en.vars.get("loop.index").map(|s| &s[..]), Some("outer"));
+        assert_eq!(en.vars.get("loop.first"), None);
+
+        let s = "(%for i in :(%i%):else nothing%)(%for i in x:(%i%):else nothing:frobnicate%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i.len(), 1);
+        assert_eq!(&res, "nothingx");
     }
 
     #[test]
@@ -595,19 +1670,371 @@ mod tests {
         assert_eq!(&s, "abc");
     }
 
-    // #[test]
-    // fn test_sort() {
-    //     let s = "(%sort +:a:c:d:b%)";
-    //     let vars = HashMap::new();
-    //     let mut en = Engine::with_predefined_commands(vars);
-    //     let (s, i) = en.process_new(s.to_string());
-    //     assert_eq!(i, vec![]);
-    //     assert_eq!(&s, "a:b:c:d");
-    //     let s = "(%sort -:a:c:d:b%)";
-    //     let (s, i) = en.process_new(s.to_string());
-    //     assert_eq!(i, vec![]);
-    //     assert_eq!(&s, "d:c:b:a");
-    // }
+    #[test]
+    fn test_process_batch() {
+        let mut vars = HashMap::new();
+        vars.insert("greeting".to_string(), "hello".to_string());
+        let en = Engine::with_predefined_commands(vars);
+        let jobs = (0..20)
+            .map(|i| {
+                let mut overlay = HashMap::new();
+                overlay.insert("n".to_string(), i.to_string());
+                RenderJob::new("(%greeting%) (%n%)(%let greeting=bye%)".to_string())
+                    .with_vars(overlay)
+            })
+            .chain(Some(RenderJob::new("(%missing%)".to_string())))
+            .collect();
+        let res = en.process_batch(jobs, 4);
+        assert_eq!(res.len(), 21);
+        for (i, (s, is)) in res.iter().take(20).enumerate() {
+            assert_eq!(is, &vec![]);
+            assert_eq!(s, &format!("hello {}", i));
+        }
+        assert_eq!(res[20].0, "");
+        assert_eq!(res[20].1.len(), 1);
+
+        let key = ProcessKey::new(
+            "echo".to_string(),
+            vec!["abc".to_string()],
+            std::env::current_dir().unwrap(),
+        );
+        let mut cache = ProcessCache::new();
+        cache.insert(key, "cached\n".to_string());
+        let mut en = Engine::with_predefined_commands(HashMap::new()).with_process_cache(cache);
+        let jobs = ["(%run echo abc%)", "(%run echo def%)", "(%run echo ghi%)"]
+            .iter()
+            .map(|s| RenderJob::new(s.to_string()))
+            .collect();
+        let (res, added) = en.process_batch_caching(jobs, 2);
+        assert_eq!(res[0].0, "cached\n");
+        assert_eq!(res[1].0, "def\n");
+        let added = added.unwrap();
+        assert_eq!(added.len(), 2);
+        let mut cache = en.take_process_cache().unwrap();
+        cache.merge(added);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let base = std::env::temp_dir().join(format!("ppm-dry-run-test-{}", std::process::id()));
+        let (input, output) = (base.join("in"), base.join("out"));
+        std::fs::create_dir_all(input.join("sub")).unwrap();
+        std::fs::write(
+            input.join("a.ppm"),
+            "<(%run touch ran%)|(%include x.txt%)|(%lsdir sub%)|(%load_ini x.ini:cfg%)>",
+        )
+        .unwrap();
+        std::fs::write(input.join("sub/b.txt"), "b").unwrap();
+
+        let dry_run = DryRun::new()
+            .with_run_output("RUN")
+            .with_include_output("INCLUDE");
+        let mut en = engine_with_include(HashMap::new())
+            .with_root_path(input.clone())
+            .with_dry_run(dry_run);
+        let (s, i) = en.process_new(std::fs::read_to_string(input.join("a.ppm")).unwrap());
+        assert_eq!(i, vec![]);
+        assert_eq!(&s, "<RUN|INCLUDE||>");
+        assert_eq!(
+            en.audit_log(),
+            &[
+                SideEffect::Run {
+                    program: "touch".to_string(),
+                    args: vec!["ran".to_string()],
+                    dir: input.clone(),
+                },
+                SideEffect::ReadFile(input.join("x.txt")),
+                SideEffect::ListDir(input.join("sub")),
+                SideEffect::ReadFile(input.join("x.ini")),
+            ]
+        );
+        assert_eq!(en.accessed_files().len(), 0);
+
+        let report = en
+            .process_tree(&input, &output, &TreeOptions::new().with_extension("ppm"))
+            .unwrap();
+        let ran = input.join("ran").exists() || output.exists();
+        std::fs::remove_dir_all(&base).unwrap();
+        assert!(!ran);
+        let effects = report
+            .side_effects()
+            .map(|(_, e)| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(effects.len(), 8);
+        assert_eq!(effects[4], format!("create directory {}", output.display()));
+        assert_eq!(effects[5], format!("write {}", output.join("a").display()));
+        assert_eq!(
+            effects[6],
+            format!("create directory {}", output.join("sub").display())
+        );
+        assert_eq!(
+            effects[7],
+            format!(
+                "copy {} to {}",
+                input.join("sub/b.txt").display(),
+                output.join("sub/b.txt").display()
+            )
+        );
+
+        let mut en = Engine::with_predefined_commands(HashMap::new())
+            .with_sandbox(Sandbox::strict())
+            .with_dry_run(DryRun::new());
+        let (_, i) = en.process_new("(%run echo%)".to_string());
+        assert_eq!(i[0].id, "sandbox:forbidden");
+        assert_eq!(en.take_audit_log(), vec![]);
+    }
+
+    #[test]
+    fn test_process_tree() {
+        let base = std::env::temp_dir().join(format!("ppm-tree-test-{}", std::process::id()));
+        let (input, output) = (base.join("in"), base.join("in/out"));
+        std::fs::create_dir_all(input.join("sub")).unwrap();
+        std::fs::write(input.join("index.html.ppm"), "<(%title%)>").unwrap();
+        std::fs::write(input.join("sub/part.txt"), "part").unwrap();
+        std::fs::write(input.join("sub/page.tpl"), "[(%include part.txt%)](%nope%)").unwrap();
+        std::fs::write(input.join("style.css"), "a {}").unwrap();
+        // a link to an ancestor is not followed
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", input.join("sub/loop")).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("title".to_string(), "hi".to_string());
+        let en = engine_with_include(vars);
+        let options = TreeOptions::new()
+            .with_extension("ppm")
+            .with_glob("sub/*.tpl");
+        let report = en.process_tree(&input, &output, &options).unwrap();
+        // renders again to check that `out` is skipped
+        let report2 = en.process_tree(&input, &output, &options).unwrap();
+        let read = |p: &str| std::fs::read_to_string(output.join(p)).unwrap();
+        let contents = (
+            read("index.html"),
+            read("sub/page.tpl"),
+            read("sub/part.txt"),
+            read("style.css"),
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(report, report2);
+        let names = report
+            .files
+            .iter()
+            .map(|f| f.output.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["index.html", "style.css", "sub/page.tpl", "sub/part.txt"]
+        );
+        assert_eq!(contents.0, "<hi>");
+        assert_eq!(contents.1, "[part]");
+        assert_eq!((&contents.2[..], &contents.3[..]), ("part", "a {}"));
+        assert_eq!(report.files[1].template, None);
+        let issues = report
+            .issues()
+            .map(|(f, i)| (f.input.to_str().unwrap(), i.id))
+            .collect::<Vec<_>>();
+        assert_eq!(issues, vec![("sub/page.tpl", "command:invalid_args")]);
+        assert_eq!(report.max_severity(), Some(Severity::Error));
+    }
+
+    #[test]
+    fn test_watch_tree() {
+        let base = std::env::temp_dir().join(format!("ppm-watch-test-{}", std::process::id()));
+        let (input, output) = (base.join("in"), base.join("out"));
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(input.join("a.ppm"), "<(%include inc.txt%)>").unwrap();
+        std::fs::write(input.join("b.ppm"), "b").unwrap();
+        std::fs::write(input.join("inc.txt"), "1").unwrap();
+        // makes sure the modification time changes, regardless of the file system's resolution
+        let touch = |p: &str, s: &str| {
+            std::fs::write(input.join(p), s).unwrap();
+            let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+            let f = std::fs::File::options()
+                .write(true)
+                .open(input.join(p))
+                .unwrap();
+            f.set_modified(later).unwrap();
+        };
+        let names = |r: TreeReport| {
+            r.files
+                .into_iter()
+                .map(|f| f.input.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let en = engine_with_include(HashMap::new());
+        let mut watcher = TreeWatcher::new(
+            input.clone(),
+            output.clone(),
+            TreeOptions::new().with_extension("ppm"),
+        );
+        let first = watcher.update(&en).unwrap();
+        assert_eq!(
+            first.files[0].dependencies.iter().collect::<Vec<_>>(),
+            vec![&input.join("inc.txt")]
+        );
+        let first = names(first);
+        let unchanged = names(watcher.update(&en).unwrap());
+        touch("inc.txt", "2");
+        let included = names(watcher.update(&en).unwrap());
+        let a = std::fs::read_to_string(output.join("a")).unwrap();
+        touch("c.ppm", "c");
+        let added = names(watcher.update(&en).unwrap());
+        watcher.invalidate();
+        let all = names(watcher.update(&en).unwrap());
+        // a file that is modified while the template that depends on it is rendered
+        touch("../outside.txt", "x");
+        std::fs::write(input.join("d.ppm"), "(%include ../outside.txt%)").unwrap();
+        let during = names(watcher.update(&en).unwrap());
+        let after = names(watcher.update(&en).unwrap());
+        let settled = names(watcher.update(&en).unwrap());
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(first, vec!["a.ppm", "b.ppm", "inc.txt"]);
+        assert_eq!(unchanged, Vec::<String>::new());
+        assert_eq!(included, vec!["a.ppm", "inc.txt"]);
+        assert_eq!(a, "<2>");
+        assert_eq!(added, vec!["c.ppm"]);
+        assert_eq!(all, vec!["a.ppm", "b.ppm", "c.ppm", "inc.txt"]);
+        assert_eq!(during, vec!["d.ppm"]);
+        assert_eq!(after, vec!["d.ppm"]);
+        assert_eq!(settled, Vec::<String>::new());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_json() {
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        en.load_json_str(
+            r#"{"user": {"name": "a:b"}, "items": [{"id": 1}, {"id": 2}, {"id": 3}], "tags": ["x", "y"]}"#,
+            "",
+        )
+        .unwrap();
+        let s = "(%user.name%) (%items.2.id%) (%for i of items:[(%i.id%)]%) (%for t of tags:(%t%)%) (%user%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "a:b 3 [1][2][3] xy name");
+
+        let path = std::env::temp_dir().join(format!("ppm-json-test-{}.json", std::process::id()));
+        std::fs::write(&path, "{\n  \"a\": [1,\n  2,,]\n}").unwrap();
+        let s = format!("(%load_json {}:data%)", path.display());
+        let (res, i) = en.process_new(s);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&res, "");
+        assert_eq!(i.len(), 1);
+        assert_eq!(i[0].id, "data:parse_error");
+        assert!(i[0].msg.contains(":3:5: "), "{}", i[0].msg);
+    }
+
+    #[test]
+    fn test_ini_env() {
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        en.load_data_str(
+            "top = 1\n; comment\n[server]\nhost = \"example.com\"\nport: 80\n",
+            DataFormat::Ini,
+            "cfg",
+        )
+        .unwrap();
+        en.load_data_str(
+            "# comment\nexport A=1\nB=\"x\\ty\" \nC='(%lit%)' \nD=d # comment\n",
+            DataFormat::Env,
+            "",
+        )
+        .unwrap();
+        let s = "(%cfg.top%) (%cfg.server.host%):(%cfg.server.port%) (%A%)(%B%)(%C%)(%D%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "1 example.com:80 1x\ty(%lit%)d");
+
+        match en.load_data_str("a = 1\nb\n", DataFormat::Ini, "") {
+            Err(DataError::Parse { pos, .. }) => assert_eq!(pos, Some(RowCol { row: 1, col: 0 })),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml() {
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        en.load_data_str(
+            "title = \"t\"\n[[pages]]\nname = \"a\"\n[[pages]]\nname = \"b\"\n",
+            DataFormat::Toml,
+            "site",
+        )
+        .unwrap();
+        let s = "(%site.title%): (%for p of site.pages:(%p.name%)%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "t: ab");
+    }
+
+    #[test]
+    fn test_csv() {
+        let dir = std::env::temp_dir();
+        let file = format!("ppm-csv-test-{}.csv", std::process::id());
+        std::fs::write(
+            dir.join(&file),
+            "name;note\r\na;\"x;\"\"y\"\"\"\r\nb;\"multi\nline\"\r\n",
+        )
+        .unwrap();
+        let mut en = Engine::with_predefined_commands(HashMap::new()).with_root_path(dir.clone());
+        let s = format!(
+            "(%for row in csv {} delimiter ;:<(%row.name%)=(%row.note%)>%)(%csv {}:delimiter ;:no_header:as raw%)(%raw.2.0%)",
+            file, file
+        );
+        let (res, i) = en.process_new(s);
+        std::fs::remove_file(dir.join(&file)).unwrap();
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "<a=x;\"y\"><b=multi\nline>b");
+        assert_eq!(en.vars.get("row.name"), None);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        let mut en = Engine::with_predefined_commands(HashMap::new());
+        let s = "(%re_sub [0-9]+:<$0>:a1b22%)|(%re_match ^a:abc%)|(%re_match ^b:abc%)|(%re_find [0-9]+:a1b22%)|(%re_find_all [0-9]+:a1b22c333%)|(%re_split ,\\s*:a, b,c%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "a<1>b<22>|true||1|1:22:333|a:b:c");
+
+        en.vars.insert("m.k".to_string(), "old".to_string());
+        let s = "(%re_captures m (?P<k>[a-z]+)=([0-9]+):a=1, bc=23:[(%m.k%)(%m.2%)](%m%)%)";
+        let (res, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&res, "[a1]a=1[bc23]bc=23");
+        assert_eq!(en.vars.get("m.k").map(|s| &s[..]), Some("old"));
+        assert_eq!(en.vars.get("m.1"), None);
+
+        let (_, i) = en.process_new("(%re_match (:x%)".to_string());
+        assert_eq!(i.len(), 1);
+        // `[0-9]+` is compiled only once
+        assert_eq!(en.regex_cache.len(), 5);
+    }
+
+    #[test]
+    fn test_sort() {
+        let s = "(%sort +:a:c:d:b%)";
+        let vars = HashMap::new();
+        let mut en = Engine::with_predefined_commands(vars);
+        let (s, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&s, "a:b:c:d");
+        let s = "(%sort -:a:c:d:b%)";
+        let (s, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&s, "d:c:b:a");
+        let s = "(%sort +/num:10:9:-1.5:x%)|(%sort +/nat:file10:file2:file1%)|(%sort -/ci:b:A:a:B%)|(%sort +/ver:1.10.0:1.2.0:1.10.0-rc.1%)";
+        let (s, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(
+            &s,
+            "-1.5:9:10:x|file1:file2:file10|b:B:A:a|1.2.0:1.10.0-rc.1:1.10.0"
+        );
+        let (_, i) = en.process_new("(%sort +/shuffle:a:b%)".to_string());
+        assert_eq!(i.len(), 1);
+    }
 
     #[test]
     fn test_sort_by() {
@@ -627,6 +2054,14 @@ mod tests {
         let (s, i) = en.process_new(s.to_string());
         assert_eq!(i, vec![]);
         assert_eq!(&s, "a:b:c:d");
+        // stable: entries with equal keys keep their order
+        en.vars.insert("e".to_string(), "10".to_string());
+        en.vars.insert("f".to_string(), "9".to_string());
+        en.vars.insert("g".to_string(), "10".to_string());
+        let s = "(%sort_by i -/num (%(%i%)%):f:e:g%)";
+        let (s, i) = en.process_new(s.to_string());
+        assert_eq!(i, vec![]);
+        assert_eq!(&s, "e:g:f");
     }
 
     // #[test]
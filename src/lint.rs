@@ -0,0 +1,268 @@
+use crate::predefined_commands::{check_for_head, check_sort_by_head, command_info, tools};
+use crate::{CommandNode, Engine, Free, Issue, Span, SyntaxNode, SyntaxTree};
+
+/// The prefixes of the variables that commands set while processing their bodies
+const IMPLICIT_PREFIXES: [&str; 2] = ["loop.", "error."];
+
+/// The commands that load data into variables, with the argument that names the variable they load into
+const DATA_COMMANDS: [(&str, &str); 5] = [
+    ("load_json", ""),
+    ("load_toml", ""),
+    ("load_ini", ""),
+    ("load_env", ""),
+    ("csv", "as "),
+];
+
+/// Unescapes a command name like the engine does before looking it up
+fn unescape_name(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.next()) {
+            ('\\', Some(n)) if Engine::<Free>::is_invalid_command_char(n) => res.push(n),
+            ('\\', Some(n)) => {
+                res.push('\\');
+                res.push(n);
+            }
+            (c, n) => {
+                res.push(c);
+                res.extend(n);
+            }
+        }
+    }
+    res
+}
+
+/// The `:`-separated arguments of a command's body (as written), with their regions
+fn args(src: &str, cmd: &CommandNode) -> Vec<(Span, String)> {
+    let body = cmd.body_span.index_str(src);
+    if body.is_empty() {
+        return vec![];
+    }
+    let mut start = 0;
+    tools::find_seps(body)
+        .into_iter()
+        .chain(std::iter::once(body.len()))
+        .map(|end| {
+            let arg = (
+                Span::new(cmd.body_span.start + start, end - start),
+                body[start..end].to_string(),
+            );
+            start = end + 1;
+            arg
+        })
+        .collect()
+}
+
+struct Linter<'a> {
+    engine: &'a Engine<Free>,
+    src: &'a str,
+    /// The names of the variables the template sets, or `None` if it may set any variable
+    bound: Option<Vec<String>>,
+    issues: Vec<Issue>,
+}
+
+impl Linter<'_> {
+    fn is_defined(&self, name: &str) -> bool {
+        let bound = match &self.bound {
+            Some(bound) => bound,
+            None => return true,
+        };
+        self.engine.vars.contains_key(name)
+            || IMPLICIT_PREFIXES.iter().any(|p| name.starts_with(p))
+            || bound.iter().any(|b| {
+                matches!(name.strip_prefix(&b[..]), Some(rest) if rest.is_empty() || rest.starts_with('.'))
+            })
+    }
+
+    /// Checks a variable name that is looked up by a command
+    fn lookup(&mut self, name: &str, span: Span) {
+        if name.contains("(%") || name.contains('\\') || self.is_defined(name) {
+            return;
+        }
+        self.issues.push(Issue {
+            id: "lint:undefined_variable:warning",
+            msg: format!("variable `{}` is never set", name),
+            span,
+        });
+    }
+
+    fn nodes(&mut self, nodes: &[SyntaxNode]) {
+        for node in nodes {
+            if let SyntaxNode::Command(cmd) = node {
+                self.command(cmd);
+            }
+        }
+    }
+
+    fn command(&mut self, cmd: &CommandNode) {
+        let name = unescape_name(&cmd.name);
+        let body = cmd.body_span.index_str(self.src);
+        // names are looked up as written, so nested commands in a name make it unknown
+        // (unless it is a variable, whose name is processed)
+        if self.engine.commands.contains_key(&name) {
+            match &name[..] {
+                // the body is never processed, so neither are the commands in it
+                "lit" => return,
+                "" | "var" | "raw" | "safe" => self.lookup(body, cmd.body_span),
+                "for" => self.for_head(cmd),
+                "sort_by" => self.sort_by_head(cmd),
+                "alt" => self.alt(cmd),
+                _ => (),
+            }
+            self.arg_count(cmd, &name);
+        } else if body.is_empty() && self.engine.commands.contains_key("") {
+            self.lookup(&name, cmd.name_span);
+        } else {
+            self.issues.push(Issue {
+                id: "command:unknown",
+                msg: format!("unknown command `{}`", name),
+                span: cmd.span,
+            });
+        }
+        self.nodes(&cmd.children);
+    }
+
+    /// Checks the number of arguments against the usage of predefined commands
+    fn arg_count(&mut self, cmd: &CommandNode, name: &str) {
+        // the output of nested commands may add arguments
+        if !cmd.children.is_empty() {
+            return;
+        }
+        let info = match command_info(name) {
+            Some(info) if !name.is_empty() => info,
+            _ => return,
+        };
+        let n = args(self.src, cmd).len();
+        if n < info.min_args() {
+            self.issues.push(Issue {
+                id: "lint:missing_args:warning",
+                msg: format!(
+                    "`{}` expects at least {} argument(s) but got {} (usage: {})",
+                    name,
+                    info.min_args(),
+                    n,
+                    info.usage
+                ),
+                span: cmd.span,
+            });
+        }
+    }
+
+    fn for_head(&mut self, cmd: &CommandNode) {
+        let (span, head) = match args(self.src, cmd).into_iter().next() {
+            Some(arg) => arg,
+            None => (cmd.body_span, String::new()),
+        };
+        // the head is processed before it is parsed
+        if head.contains("(%") || head.contains('\\') {
+            return;
+        }
+        if let Err(msg) = check_for_head(&head) {
+            self.issues.push(Issue {
+                id: "command:invalid_args",
+                msg,
+                span,
+            });
+        }
+    }
+
+    fn sort_by_head(&mut self, cmd: &CommandNode) {
+        let args = args(self.src, cmd);
+        let (span, head) = match args.first() {
+            Some(arg) => arg.clone(),
+            None => (cmd.body_span, String::new()),
+        };
+        // the variable and the order are never processed, but the key expression is
+        let words = head.splitn(3, ' ').take(2).collect::<String>();
+        if words.contains("(%") {
+            return;
+        }
+        if let Err(msg) = check_sort_by_head(&head, args.len() > 1) {
+            self.issues.push(Issue {
+                id: "command:invalid_args",
+                msg,
+                span,
+            });
+        }
+    }
+
+    /// Finds alternatives after one that is never empty
+    fn alt(&mut self, cmd: &CommandNode) {
+        let args = args(self.src, cmd);
+        let first = args
+            .iter()
+            .position(|(_, arg)| !arg.is_empty() && !arg.contains("(%"));
+        if let Some(i) = first.filter(|&i| i + 1 < args.len()) {
+            let start = args[i + 1].0.start;
+            self.issues.push(Issue {
+                id: "lint:unreachable:warning",
+                msg: format!(
+                    "alternatives after `{}` are never output since it is never empty",
+                    args[i].1
+                ),
+                span: Span::new(start, cmd.body_span.end() - start),
+            });
+        }
+    }
+}
+
+/// Finds the names of the variables a template sets, or `None` if it may set any variable
+fn bound_vars(tree: &SyntaxTree, src: &str) -> Option<Vec<String>> {
+    if tree.has_computed_bindings(src) {
+        return None;
+    }
+    let mut res = tree
+        .bindings(src)
+        .into_iter()
+        .map(|b| b.name)
+        .collect::<Vec<_>>();
+    for cmd in tree.commands() {
+        let prefix = match DATA_COMMANDS.iter().find(|(name, _)| cmd.name == *name) {
+            Some((_, prefix)) => prefix,
+            None => continue,
+        };
+        let args = args(src, cmd);
+        let target = if prefix.is_empty() {
+            args.get(1).map(|(_, arg)| arg.trim())
+        } else {
+            args.iter().find_map(|(_, arg)| arg.strip_prefix(prefix))
+        };
+        match target {
+            Some(name) if !name.is_empty() && !name.contains("(%") => res.push(name.to_string()),
+            // loaded at the top level, or under a computed name
+            _ => return None,
+        }
+    }
+    Some(res)
+}
+
+impl Engine<Free> {
+    /// Finds likely mistakes in a template without processing it, so without any side effects
+    ///
+    /// Reports
+    /// - unbalanced delimiters and unterminated comments
+    /// - unknown commands (like processing would)
+    /// - variables that are neither in `self.vars` nor set by the template
+    ///   (unless the template loads data without a name to load it under or sets a variable with a computed name)
+    /// - arguments of `alt` that can't be output because an argument before them is never empty
+    /// - invalid heads of `for` and `sort_by`, unless they are computed by nested commands
+    /// - predefined commands with fewer arguments than their [usage](predefined_commands/struct.CommandInfo.html#method.min_args) requires
+    ///
+    /// Commands inside `lit` are not checked since they are never processed.
+    /// Variables are checked regardless of where the template sets them,
+    /// so a variable used before it is set is not reported
+    pub fn lint(&self, src: &str) -> Vec<Issue> {
+        let tree = SyntaxTree::parse(src);
+        let mut linter = Linter {
+            engine: self,
+            src,
+            bound: bound_vars(&tree, src),
+            issues: tree.issues.clone(),
+        };
+        linter.nodes(&tree.nodes);
+        let mut issues = linter.issues;
+        issues.sort_by_key(|i| i.span.start);
+        issues
+    }
+}
@@ -1,9 +1,34 @@
+pub use self::branch::handler as match_handler;
+#[cfg(feature = "chrono")]
+pub use self::date::handler as date_handler;
+pub use self::diagnostics::{assert_handler, error_handler, warn_handler};
+pub use self::escape::{
+    c_str_handler, html_handler, json_str_handler, rust_str_handler, shell_quote_handler,
+    url_handler, xml_handler,
+};
+pub(crate) use self::for_loop::check_head as check_for_head;
 pub use self::for_loop::handler as for_handler;
+pub use self::info::{command_info, CommandInfo, COMMAND_INFO};
+#[cfg(feature = "serde_json")]
+pub use self::load::load_json_handler;
+#[cfg(feature = "toml")]
+pub use self::load::load_toml_handler;
+pub use self::load::{csv_handler, load_env_handler, load_ini_handler};
 pub use self::lsdir::handler as lsdir_handler;
 #[cfg(feature = "regex")]
-pub use self::regex::handler as regex_sub_handler;
+pub use self::regex::{
+    captures_handler as regex_captures_handler, find_all_handler as regex_find_all_handler,
+    find_handler as regex_find_handler, handler as regex_sub_handler,
+    match_handler as regex_match_handler, split_handler as regex_split_handler,
+};
+pub(crate) use self::sort::check_by_head as check_sort_by_head;
+pub use self::sort::{by_handler as sort_by_handler, handler as sort_handler};
+pub use self::while_loop::handler as while_handler;
 use crate::util::{head_tail, make_absolute, SplitNotEscapedString};
-use crate::{shell_util, CommandConfig, CommandHandler, Issue};
+use crate::{
+    shell_util, CommandConfig, CommandHandler, Issue, LoopControl, ProcessCache, ProcessKey,
+    Severity, SideEffect, Span,
+};
 use std::collections::HashMap;
 use std::path::Path;
 
@@ -14,10 +39,19 @@ use std::path::Path;
 /// Some tools to ease creating commands.
 pub mod tools;
 
+mod branch;
+#[cfg(feature = "chrono")]
+mod date;
+mod diagnostics;
+mod escape;
 mod for_loop;
+mod info;
+mod load;
 mod lsdir;
 #[cfg(feature = "regex")]
 mod regex;
+mod sort;
+mod while_loop;
 
 /// Creates a `HashMap` with all the predefined basic commands.
 ///
@@ -28,13 +62,42 @@ mod regex;
 ///     -> this is a special case, for which the engine knwos to view `(%cmd%)`
 /// as the command `cmd` if it exists and as the empty command with argument `cmd` otherwise
 /// - `var` for [`get_var_handler`](fn.get_var_handler.html)
+/// - `raw` and `safe` for [`raw_var_handler`](fn.raw_var_handler.html)
 /// - `let` for [`set_var_handler`](fn.set_var_handler.html)
 /// - `run` for [`run_process_handler`](fn.run_process_handler.html)
 /// - `alt` for [`fallback_handler`](fn.fallback_handler.html)
+/// - `match` for [`match_handler`](fn.match_handler.html)
+/// - `try` for [`try_handler`](fn.try_handler.html)
+/// - `warn` for [`warn_handler`](fn.warn_handler.html)
+/// - `error` for [`error_handler`](fn.error_handler.html)
+/// - `assert` for [`assert_handler`](fn.assert_handler.html)
 /// - `lsdir` for [`lsdir_handler`](fn.lsdir_handler.html)
 /// - `re_sub` for [`regex_sub_handler`](fn.regex_sub_handler.html)
+/// - `re_match` for [`regex_match_handler`](fn.regex_match_handler.html)
+/// - `re_find` for [`regex_find_handler`](fn.regex_find_handler.html)
+/// - `re_find_all` for [`regex_find_all_handler`](fn.regex_find_all_handler.html)
+/// - `re_captures` for [`regex_captures_handler`](fn.regex_captures_handler.html)
+/// - `re_split` for [`regex_split_handler`](fn.regex_split_handler.html)
 /// - `for` for [`for_handler`](fn.for_handler.html)
-/// - `sort_by` for [`sort_by_handler`](fn.sort_handler.html)
+/// - `while` for [`while_handler`](fn.while_handler.html)
+/// - `break` for [`break_handler`](fn.break_handler.html)
+/// - `continue` for [`continue_handler`](fn.continue_handler.html)
+/// - `sort` for [`sort_handler`](fn.sort_handler.html)
+/// - `sort_by` for [`sort_by_handler`](fn.sort_by_handler.html)
+/// - `env` for [`env_handler`](fn.env_handler.html)
+/// - `date` for [`date_handler`](fn.date_handler.html)
+/// - `load_json` for [`load_json_handler`](fn.load_json_handler.html)
+/// - `load_toml` for [`load_toml_handler`](fn.load_toml_handler.html)
+/// - `load_ini` for [`load_ini_handler`](fn.load_ini_handler.html)
+/// - `load_env` for [`load_env_handler`](fn.load_env_handler.html)
+/// - `csv` for [`csv_handler`](fn.csv_handler.html)
+/// - `html` for [`html_handler`](fn.html_handler.html)
+/// - `xml` for [`xml_handler`](fn.xml_handler.html)
+/// - `json_str` for [`json_str_handler`](fn.json_str_handler.html)
+/// - `url` for [`url_handler`](fn.url_handler.html)
+/// - `shell_quote` for [`shell_quote_handler`](fn.shell_quote_handler.html)
+/// - `c_str` for [`c_str_handler`](fn.c_str_handler.html)
+/// - `rust_str` for [`rust_str_handler`](fn.rust_str_handler.html)
 #[inline]
 pub fn get_all_handlers() -> HashMap<String, CommandHandler> {
     let mut res = HashMap::new();
@@ -42,14 +105,148 @@ pub fn get_all_handlers() -> HashMap<String, CommandHandler> {
     res.insert("eval".to_string(), eval_handler as _);
     res.insert("".to_string(), get_var_handler as _);
     res.insert("var".to_string(), get_var_handler as _);
+    res.insert("raw".to_string(), raw_var_handler as _);
+    res.insert("safe".to_string(), raw_var_handler as _);
     res.insert("let".to_string(), set_var_handler as _);
     res.insert("run".to_string(), run_process_handler as _);
     res.insert("alt".to_string(), fallback_handler as _);
+    res.insert("match".to_string(), match_handler as _);
+    res.insert("try".to_string(), try_handler as _);
+    res.insert("warn".to_string(), warn_handler as _);
+    res.insert("error".to_string(), error_handler as _);
+    res.insert("assert".to_string(), assert_handler as _);
+    res.insert("sort".to_string(), sort_handler as _);
     res.insert("sort_by".to_string(), sort_by_handler as _);
     res.insert("lsdir".to_string(), lsdir_handler as _);
     #[cfg(feature = "regex")]
-    res.insert("re_sub".to_string(), regex_sub_handler as _);
+    {
+        res.insert("re_sub".to_string(), regex_sub_handler as _);
+        res.insert("re_match".to_string(), regex_match_handler as _);
+        res.insert("re_find".to_string(), regex_find_handler as _);
+        res.insert("re_find_all".to_string(), regex_find_all_handler as _);
+        res.insert("re_captures".to_string(), regex_captures_handler as _);
+        res.insert("re_split".to_string(), regex_split_handler as _);
+    }
     res.insert("for".to_string(), for_handler as _);
+    res.insert("while".to_string(), while_handler as _);
+    res.insert("break".to_string(), break_handler as _);
+    res.insert("continue".to_string(), continue_handler as _);
+    res.insert("env".to_string(), env_handler as _);
+    #[cfg(feature = "chrono")]
+    res.insert("date".to_string(), date_handler as _);
+    #[cfg(feature = "serde_json")]
+    res.insert("load_json".to_string(), load_json_handler as _);
+    #[cfg(feature = "toml")]
+    res.insert("load_toml".to_string(), load_toml_handler as _);
+    res.insert("load_ini".to_string(), load_ini_handler as _);
+    res.insert("load_env".to_string(), load_env_handler as _);
+    res.insert("csv".to_string(), csv_handler as _);
+    res.insert("html".to_string(), html_handler as _);
+    res.insert("xml".to_string(), xml_handler as _);
+    res.insert("json_str".to_string(), json_str_handler as _);
+    res.insert("url".to_string(), url_handler as _);
+    res.insert("shell_quote".to_string(), shell_quote_handler as _);
+    res.insert("c_str".to_string(), c_str_handler as _);
+    res.insert("rust_str".to_string(), rust_str_handler as _);
+    res
+}
+
+/// Groups of predefined commands, so that only some of them can be enabled (see [`get_handlers`](fn.get_handlers.html))
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CommandSet {
+    /// Commands that only work with their arguments and the engine's variables,
+    /// like `let`, `for` or `match`
+    Core,
+    /// The escaping commands, like `html` or `shell_quote`
+    Escape,
+    /// Commands that read files, like `include` or `load_json`
+    Fs,
+    /// The `run` command
+    Process,
+    /// Commands that read the environment of the process, `env` and `date`
+    System,
+    /// The commands for regular expressions, like `re_sub`
+    Regex,
+}
+
+impl CommandSet {
+    /// All command sets, in the order they are declared
+    pub const ALL: [CommandSet; 6] = [
+        CommandSet::Core,
+        CommandSet::Escape,
+        CommandSet::Fs,
+        CommandSet::Process,
+        CommandSet::System,
+        CommandSet::Regex,
+    ];
+
+    /// The name of the command set, in lowercase
+    pub fn name(self) -> &'static str {
+        match self {
+            CommandSet::Core => "core",
+            CommandSet::Escape => "escape",
+            CommandSet::Fs => "fs",
+            CommandSet::Process => "process",
+            CommandSet::System => "system",
+            CommandSet::Regex => "regex",
+        }
+    }
+
+    /// Looks up a command set by its [`name`](#method.name)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|s| s.name() == name)
+    }
+
+    /// The names of the commands in this set
+    ///
+    /// Commands that require a disabled feature are included nonetheless
+    pub fn commands(self) -> &'static [&'static str] {
+        match self {
+            CommandSet::Core => &[
+                "lit", "eval", "", "var", "raw", "safe", "let", "alt", "match", "try", "for",
+                "while", "break", "continue", "sort", "sort_by", "warn", "error", "assert",
+            ],
+            CommandSet::Escape => &[
+                "html",
+                "xml",
+                "json_str",
+                "url",
+                "shell_quote",
+                "c_str",
+                "rust_str",
+            ],
+            CommandSet::Fs => &[
+                "include",
+                "lsdir",
+                "load_json",
+                "load_toml",
+                "load_ini",
+                "load_env",
+                "csv",
+            ],
+            CommandSet::Process => &["run"],
+            CommandSet::System => &["env", "date"],
+            CommandSet::Regex => &[
+                "re_sub",
+                "re_match",
+                "re_find",
+                "re_find_all",
+                "re_captures",
+                "re_split",
+            ],
+        }
+    }
+}
+
+/// Creates a `HashMap` with the predefined commands in the given sets
+///
+/// See [`get_all_handlers`](fn.get_all_handlers.html) for their names.
+/// Unlike it, this also contains `include` for [`include_handler`](fn.include_handler.html)
+/// if [`CommandSet::Fs`](enum.CommandSet.html#variant.Fs) is given, since the sets are enabled explicitly
+pub fn get_handlers(sets: &[CommandSet]) -> HashMap<String, CommandHandler> {
+    let mut res = get_all_handlers();
+    res.insert("include".to_string(), include_handler as _);
+    res.retain(|k, _| sets.iter().any(|s| s.commands().contains(&&k[..])));
     res
 }
 
@@ -67,9 +264,21 @@ pub fn literal_handler(cfg: CommandConfig) -> String {
 
 /// substitutes calls to variables stored in `engine.vars` with their value
 /// - argument: anything - the variable name
+/// - if the engine has an auto-escaper (see [`Engine::with_auto_escape`](../struct.Engine.html#method.with_auto_escape)),
+///   the value is escaped with it
 /// - does not call `engine.process` on its argument before processing and on the final value
-pub fn get_var_handler(mut cfg: CommandConfig) -> String {
-    let key = cfg.process_body();
+pub fn get_var_handler(cfg: CommandConfig) -> String {
+    let escaper = cfg.engine.auto_escape;
+    let val = raw_var_handler(cfg);
+    match escaper {
+        Some(e) => e.escape(&val),
+        None => val,
+    }
+}
+
+/// like [`get_var_handler`](fn.get_var_handler.html), but never escapes the value
+pub fn raw_var_handler(mut cfg: CommandConfig) -> String {
+    let key = cfg.without_auto_escape(CommandConfig::process_body);
     let err = cfg.invalid_args(format!("unknown variable: {}", key));
     let issues = cfg.issues;
     cfg.engine.vars.get(&key).cloned().unwrap_or_else(|| {
@@ -82,7 +291,8 @@ pub fn get_var_handler(mut cfg: CommandConfig) -> String {
 /// - arguments: the name of the variable. The block is the value the variable is set to
 /// - calls `engine.process` on its argument string before doing anything
 pub fn set_var_handler(mut cfg: CommandConfig) -> String {
-    let body = cfg.process_body();
+    // the value is escaped when it is substituted
+    let body = cfg.without_auto_escape(CommandConfig::process_body);
 
     let mut spl = body
         .split_not_escaped::<Vec<_>>('=', '\\', false)
@@ -95,11 +305,83 @@ pub fn set_var_handler(mut cfg: CommandConfig) -> String {
     String::new()
 }
 
+fn loop_control_impl(mut cfg: CommandConfig, signal: LoopControl, name: &str) -> String {
+    if !cfg.body.is_empty()
+        && !tools::is_truthy(&cfg.without_auto_escape(CommandConfig::process_body))
+    {
+        return String::new();
+    }
+    if !cfg.signal_loop(signal) {
+        cfg.issues.push(Issue {
+            id: "control_flow:outside_loop",
+            msg: format!("`{}` outside of a loop", name),
+            span: cfg.cmd_span,
+        });
+    }
+    String::new()
+}
+
+/// ends the innermost enclosing loop (like [`for`](fn.for_handler.html) or [`while`](fn.while_handler.html))
+/// - arguments: optionally a condition, in which case it only does so if the condition is true
+///   (see [`is_truthy`](tools/fn.is_truthy.html))
+/// - everything after it up to the end of the loop body is not processed,
+///   but the output of the current iteration up to this point is kept
+/// - outside of a loop, this is an issue
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn break_handler(cfg: CommandConfig) -> String {
+    loop_control_impl(cfg, LoopControl::Break, "break")
+}
+
+/// ends the current iteration of the innermost enclosing loop (like [`for`](fn.for_handler.html) or [`while`](fn.while_handler.html))
+/// and continues with the next one
+/// - otherwise the same as [`break_handler`](fn.break_handler.html)
+#[inline]
+pub fn continue_handler(cfg: CommandConfig) -> String {
+    loop_control_impl(cfg, LoopControl::Continue, "continue")
+}
+
+/// reads an environment variable of the process
+/// - arguments: the name of the variable and optionally a fallback value, separated by a colon
+///   (using [`splitn_args`](tools/fn.splitn_args.html))
+/// - outputs the fallback value if the variable is not set or the sandbox forbids reading it
+///     - without a fallback value, this is an issue
+/// - calls `engine.process` on its argument string before doing anything
+///     - the name is processed without the engine's auto-escaper, the fallback value with it
+pub fn env_handler(mut cfg: CommandConfig) -> String {
+    let mut spl = tools::splitn_args(2, cfg.body.clone()).into_iter();
+    let name = spl.next().unwrap();
+    let name = cfg.without_auto_escape(|cfg| cfg.process(name));
+    let fallback = spl.next().map(|s| cfg.process(s));
+
+    if name.is_empty() {
+        cfg.push_missing_args("no environment variable name given");
+        return fallback.unwrap_or_default();
+    }
+
+    if !cfg.engine.sandbox.allow_env {
+        let issue = cfg.forbidden("reading environment variables");
+        cfg.issues.push(issue);
+        return fallback.unwrap_or_default();
+    }
+
+    match (std::env::var(&name), fallback) {
+        (Ok(val), _) => val,
+        (Err(_), Some(fallback)) => fallback,
+        (Err(e), None) => {
+            cfg.push_invalid_args(format!("environment variable {}: {}", name, e));
+            String::new()
+        }
+    }
+}
+
 /// runs a process based on the argument
 /// - argument: a basic shell-like syntax for spawning a process (supports string literals for escaping spaces)
-/// - calls `engine.process` on its argument string before doing anything
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+/// - if the engine has a [`ProcessCache`](../struct.ProcessCache.html), the output is taken from it when possible
+/// - in a dry run, the process is not spawned and the placeholder is output instead
 pub fn run_process_handler(mut cfg: CommandConfig) -> String {
-    let v = shell_util::split_args(&cfg.process_body());
+    let v = shell_util::split_args(&cfg.without_auto_escape(CommandConfig::process_body));
     // the `split_args` will always at least produce an empty string for `cmd`
     let (cmd, argv) = head_tail(v).unwrap();
 
@@ -108,15 +390,55 @@ pub fn run_process_handler(mut cfg: CommandConfig) -> String {
         return String::new();
     }
 
-    let output = match cfg.engine.root_path.clone() {
-        Some(cwd) => std::process::Command::new(cmd)
-            .args(argv)
-            .current_dir(&cwd)
-            .output(),
-        None => std::process::Command::new(cmd).args(argv).output(),
+    if !cfg.engine.sandbox.allow_processes {
+        let issue = cfg.forbidden("running processes");
+        cfg.issues.push(issue);
+        return String::new();
+    }
+
+    let cwd = match cfg
+        .engine
+        .root_path
+        .clone()
+        .map_or_else(std::env::current_dir, Ok)
+    {
+        Ok(x) => x,
+        Err(e) => {
+            cfg.issues.push(Issue::io_error(
+                e,
+                cfg.cmd_span,
+                Some("while trying to get the current directory"),
+            ));
+            return String::new();
+        }
+    };
+
+    let effect = SideEffect::Run {
+        program: cmd.clone(),
+        args: argv.clone(),
+        dir: cwd.clone(),
     };
+    if let Some(out) = cfg.dry_run(effect) {
+        return out;
+    }
 
-    let output = match output {
+    let key = cfg
+        .engine
+        .process_cache
+        .as_ref()
+        .map(|_| ProcessKey::new(cmd.clone(), argv.clone(), cwd.clone()));
+    let cached = |cache: Option<&ProcessCache>| Some(cache?.get(key.as_ref()?)?.to_string());
+    if let Some(out) = cached(cfg.engine.process_cache.as_ref())
+        .or_else(|| cached(cfg.engine.shared_process_cache.as_deref()))
+    {
+        return out;
+    }
+
+    let output = match std::process::Command::new(cmd)
+        .args(argv)
+        .current_dir(&cwd)
+        .output()
+    {
         Ok(x) => x,
         Err(e) => {
             cfg.issues.push(Issue::io_error(
@@ -128,14 +450,21 @@ pub fn run_process_handler(mut cfg: CommandConfig) -> String {
         }
     };
 
-    String::from_utf8_lossy(&output.stdout).to_string()
+    let out = String::from_utf8_lossy(&output.stdout).to_string();
+    if let (Some(cache), Some(key)) = (cfg.engine.process_cache.as_mut(), key) {
+        if output.status.success() {
+            cache.insert(key, out.clone());
+        }
+    }
+    out
 }
 
 /// outputs the first of its arguments that is not empty
 /// - arguments: separated by colons (using [`split_args`](tools/split_args.html))
 ///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
 /// - short circuits
-/// - calls `engine.process` on its argument string before doing anything
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+///     - the output is escaped with it instead
 pub fn fallback_handler(mut cfg: CommandConfig) -> String {
     // let spl = cfg
     //     .process_body()
@@ -145,7 +474,9 @@ pub fn fallback_handler(mut cfg: CommandConfig) -> String {
     //     .collect::<Vec<_>>();
 
     // because it is already processed, we don't need tools::split_args here
-    let spl: Vec<String> = cfg.process_body().split_not_escaped(':', '\\', false);
+    let spl: Vec<String> = cfg
+        .without_auto_escape(CommandConfig::process_body)
+        .split_not_escaped(':', '\\', false);
 
     // let mut cumulen = None;
     for s in spl {
@@ -165,7 +496,7 @@ pub fn fallback_handler(mut cfg: CommandConfig) -> String {
         //     .collect::<String>();
 
         if !s.is_empty() {
-            return s;
+            return cfg.auto_escape(s);
         }
     }
 
@@ -173,12 +504,71 @@ pub fn fallback_handler(mut cfg: CommandConfig) -> String {
     String::new()
 }
 
+const ERROR_VARS: [&str; 3] = ["error.id", "error.msg", "error.count"];
+
+/// processes its body and outputs a fallback instead if that caused errors
+/// - arguments: the body and the fallback, separated by a colon (using [`splitn_args`](tools/fn.splitn_args.html))
+/// - if processing the body causes issues with [`Severity::Error`](../enum.Severity.html), they are discarded
+///   and the fallback is processed and output instead
+///     - while processing the fallback, the variables `error.id` and `error.msg` hold the id and message of the first error
+///       and `error.count` holds the number of errors
+///     - it overwrites any previous value these names had, but restores them once finished
+///     - warnings are always kept
+/// - otherwise, the output of the body is output and all issues are kept
+/// - calls `engine.process` on the body before doing anything
+pub fn try_handler(mut cfg: CommandConfig) -> String {
+    // note: the fallback may only be processed if it is needed, thus the body can't be processed beforehand
+    let mut args = tools::splitn_args_with_len(2, cfg.body.clone()).into_iter();
+    let (body, body_len) = args.next().unwrap();
+    let fallback = args.next().map(|(s, _)| s).unwrap_or_default();
+
+    let mut issues = vec![];
+    let res = cfg
+        .process_subbody_into(body, Span::new(0, body_len), &mut issues)
+        .unwrap_or_default();
+    let (errors, warnings): (Vec<_>, Vec<_>) = issues
+        .into_iter()
+        .partition(|i| i.severity() == Severity::Error);
+    cfg.issues.extend(warnings);
+
+    let first = match errors.first() {
+        Some(x) => x,
+        None => return res,
+    };
+    let prev = ERROR_VARS
+        .iter()
+        .map(|&k| cfg.engine.vars.remove(k))
+        .collect::<Vec<_>>();
+    let vals = [
+        first.id.to_string(),
+        first.msg.clone(),
+        errors.len().to_string(),
+    ];
+    for (k, v) in ERROR_VARS.iter().zip(vals.iter()) {
+        cfg.engine.vars.insert(k.to_string(), v.clone());
+    }
+    let res = cfg.process(fallback);
+    for (k, prev) in ERROR_VARS.iter().zip(prev) {
+        match prev {
+            Some(x) => cfg.engine.vars.insert(k.to_string(), x),
+            None => cfg.engine.vars.remove(*k),
+        };
+    }
+    res
+}
+
 /// includes another file inside a file
 /// - argument: the path to the file
-/// - calls `engine.process` on its argument string before doing anything
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
 /// - does not call `engine.process` on the file before inserting it
+/// - in a dry run, the file is not read and the placeholder is output instead
 pub fn include_handler(mut cfg: CommandConfig) -> String {
-    let arg = cfg.process_body();
+    let arg = cfg.without_auto_escape(CommandConfig::process_body);
+    if !cfg.engine.sandbox.allow_fs {
+        let issue = cfg.forbidden("reading files");
+        cfg.issues.push(issue);
+        return String::new();
+    }
     let path = match make_absolute(Path::new(&arg), cfg.engine.root_path.clone()) {
         Ok(x) => x,
         Err(e) => {
@@ -191,6 +581,10 @@ pub fn include_handler(mut cfg: CommandConfig) -> String {
         }
     };
 
+    if let Some(out) = cfg.dry_run(SideEffect::ReadFile(path.clone())) {
+        return out;
+    }
+    cfg.record_access(path.clone());
     match std::fs::read_to_string(path) {
         Ok(s) => s,
         Err(e) => {
@@ -205,7 +599,7 @@ pub fn include_handler(mut cfg: CommandConfig) -> String {
 }
 
 #[inline]
-fn join_reescape_colon(i: impl Iterator<Item = String>) -> String {
+pub(crate) fn join_reescape_colon(i: impl Iterator<Item = String>) -> String {
     i.map(|s| {
         s.chars()
             .flat_map(|c| {
@@ -245,97 +639,4 @@ fn join_reescape_colon(i: impl Iterator<Item = String>) -> String {
 //     res
 // }
 
-// /// Sorts a `:`-separated list
-// /// - calls `engine.process` on the entire argument before doing anything
-// /// - the first argument specifies the sorting order
-// ///     - valid values are: `+`, `asc`, `ascending`, `inc`, `increasing`, `-`, `desc`, `descending`, `dec`, `decreasing`
-// /// - outputs a `:`-separated list, just sorted
-// pub fn sort_handler(mut cfg: CommandConfig) -> String {
-//     let body = cfg.process_body();
-//     let mut args = body.split_not_escaped(':', '\\', false);
-//     let first_arg = args.remove(0);
-//     let desc = match first_arg.as_str() {
-//         "+" | "asc" | "ascending" | "inc" | "increasing" => false,
-//         "-" | "desc" | "descending" | "dec" | "decreasing" => true,
-//         s => {
-//             cfg.push_invalid_args(format!("invalid sorting order: {}", s));
-//             return join_reescape_colon(args.into_iter());
-//         }
-//     };
-//     args.sort();
-//     if desc {
-//         args.reverse();
-//     }
-//     join_reescape_colon(args.into_iter())
-// }
-
-/// Sorts a `:`-separated list, according to a key
-/// - arguments: separated by colons (using [`split_args`](tools/split_args.html))
-/// - calls `engine.process` on the entire second argument before doing anything
-/// - the first argument follows the syntax `<variable> <order> <expr>`
-///     - `<variable>` is the variable by which the current element can be referenced in `<expr>`
-///     - `<order>` is one of `+`, `asc`, `ascending`, `inc`, `increasing`, `-`, `desc`, `descending`, `dec`, `decreasing`
-///     - `<expr>` is the expression by which the entries will be compared
-/// - outputs a sorted `:`-separated list
-pub fn sort_by_handler(mut cfg: CommandConfig) -> String {
-    // let mut args = cfg
-    //     .body
-    //     .splitn_not_escaped::<Vec<_>>(2, ':', '\\', false)
-    //     .into_iter();
-
-    // note: a part of the first argument has access to the loop variable, thus it can't be processed beforehand
-    let mut args = tools::splitn_args(2, cfg.body.clone()).into_iter();
-    let first_arg = args.next().unwrap();
-    let mut spl = first_arg
-        .splitn_not_escaped::<Vec<_>>(3, ' ', '\\', false)
-        .into_iter();
-    let var = spl.next().unwrap();
-    let desc = match spl.next().as_deref() {
-        Some(s) => match s {
-            "+" | "asc" | "ascending" | "inc" | "increasing" => false,
-            "-" | "desc" | "descending" | "dec" | "decreasing" => true,
-            _ => {
-                cfg.push_invalid_args(format!("invalid sorting order: {}", s));
-                return args.next().unwrap_or_default();
-            }
-        },
-        None => {
-            cfg.push_invalid_args("expected sorting order, got end of argument".to_string());
-            return args.next().unwrap_or_default();
-        }
-    };
-    let expr = match spl.next() {
-        Some(x) => x,
-        None => {
-            cfg.push_invalid_args("no map expression provided".to_string());
-            return args.next().unwrap_or_default();
-        }
-    };
-
-    let args = match args.next() {
-        Some(x) => x,
-        None => {
-            cfg.push_invalid_args("no list to sort provided".to_string());
-            return String::new();
-        }
-    };
-    // because it is already processed, we don't need tools::split_args here
-    let mut args = cfg
-        .process(args)
-        .split_not_escaped::<Vec<_>>(':', '\\', false);
-
-    let orig_var = cfg.engine.vars.remove(&var);
-    args.sort_by_key(|s| {
-        cfg.engine.vars.insert(var.clone(), s.clone());
-        cfg.process(expr.to_string())
-    });
-    if desc {
-        args.reverse();
-    }
-    if let Some(s) = orig_var {
-        cfg.engine.vars.insert(var, s);
-    }
-    join_reescape_colon(args.into_iter())
-}
-
 // maybe_todo: macros
@@ -0,0 +1,123 @@
+use super::tools;
+use crate::util::glob_matches;
+use crate::{CommandConfig, Issue};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Pattern {
+    /// `_`, which matches everything
+    Wildcard,
+    /// A pattern containing `*` or `?`
+    Glob(String),
+    /// A regular expression, written as `/<regex>/`
+    #[cfg(feature = "regex")]
+    Regex(String),
+    Literal(String),
+}
+
+impl Pattern {
+    fn new(s: String) -> Self {
+        #[cfg(feature = "regex")]
+        {
+            if s.len() >= 2 && s.starts_with('/') && s.ends_with('/') {
+                return Pattern::Regex(s[1..s.len() - 1].to_string());
+            }
+        }
+        if s == "_" {
+            Pattern::Wildcard
+        } else if has_unescaped_wildcard(&s) {
+            Pattern::Glob(s)
+        } else {
+            Pattern::Literal(unescape_wildcards(&s))
+        }
+    }
+
+    #[cfg_attr(not(feature = "regex"), allow(unused_variables))]
+    fn matches(&self, cfg: &mut CommandConfig, value: &str) -> Result<bool, Issue> {
+        Ok(match self {
+            Pattern::Wildcard => true,
+            Pattern::Glob(pat) => glob_matches(pat, value),
+            #[cfg(feature = "regex")]
+            Pattern::Regex(re) => super::regex::compile(cfg, re)?.is_match(value),
+            Pattern::Literal(s) => s == value,
+        })
+    }
+}
+
+fn has_unescaped_wildcard(s: &str) -> bool {
+    let mut esc = false;
+    for c in s.chars() {
+        match c {
+            _ if esc => esc = false,
+            '\\' => esc = true,
+            '*' | '?' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// Removes the backslashes in front of `*`, `?` and `\`
+fn unescape_wildcards(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut iter = s.chars().peekable();
+    while let Some(c) = iter.next() {
+        match (c, iter.peek()) {
+            ('\\', Some(&n)) if "*?\\".contains(n) => {
+                res.push(n);
+                iter.next();
+            }
+            (c, _) => res.push(c),
+        }
+    }
+    res
+}
+
+/// selects one of multiple outputs based on which pattern a value matches
+/// - arguments: separated by colons (using [`split_args`](tools/split_args.html))
+///     - the first argument is the value
+///     - the remaining arguments (the arms) have the form `<pattern> => <output>`
+///         - whitespace around the pattern and the output is ignored
+/// - the output of the first arm whose pattern matches the value is processed and output,
+///   all other outputs are not processed
+///     - if no arm matches, nothing is output, which is an issue
+/// - patterns can be
+///     - `_`, which matches everything
+///     - (requires the `regex` feature) a regular expression surrounded by slashes (`/<regex>/`),
+///       which matches if it matches somewhere in the value (use `^` and `$` to match the whole value)
+///     - a glob pattern, if it contains `*` (matching any sequence of characters) or `?` (matching any single character)
+///         - escaping these with `'\\'` is supported
+///     - otherwise, a literal string that matches only itself
+/// - calls `engine.process` on the value before doing anything and on the patterns before matching them,
+///   both without the engine's auto-escaper
+pub fn handler(mut cfg: CommandConfig) -> String {
+    // note: only the selected output may be processed, thus the body can't be processed beforehand
+    let mut args = tools::split_args(cfg.body.clone()).into_iter();
+    let value = args.next().unwrap();
+    let value = cfg
+        .without_auto_escape(|cfg| cfg.process(value))
+        .trim()
+        .to_string();
+
+    for arm in args {
+        let (pat, out) = match arm.find("=>") {
+            Some(i) => (&arm[..i], &arm[i + 2..]),
+            None => {
+                cfg.push_invalid_args(format!("missing `=>` in match arm: {}", arm.trim()));
+                continue;
+            }
+        };
+        let pat = cfg.without_auto_escape(|cfg| cfg.process(pat.trim().to_string()));
+        let pat = Pattern::new(pat);
+        match pat.matches(&mut cfg, &value) {
+            Ok(true) => return cfg.process(out.trim().to_string()),
+            Ok(false) => (),
+            Err(e) => cfg.issues.push(e),
+        }
+    }
+    cfg.issues.push(Issue {
+        id: "command:match:no_arm",
+        msg: format!("no match arm matches `{}`", value),
+        span: cfg.cmd_span,
+    });
+    String::new()
+}
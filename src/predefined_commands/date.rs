@@ -0,0 +1,197 @@
+use crate::util::SplitNotEscapedString;
+use crate::{CommandConfig, Issue};
+use chrono::{DateTime, Duration, Local, Months, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Tz {
+    Utc,
+    Local,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Offset {
+    Duration(Duration),
+    Months(i64),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct DateConfig {
+    format: String,
+    tz: Tz,
+    offsets: Vec<Offset>,
+    parse: Option<String>,
+    parse_format: Option<String>,
+}
+
+fn parse_offset(s: &str) -> Option<Offset> {
+    let split = s
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(s.len(), |(i, _)| i);
+    let n = s[..split].parse::<i64>().ok()?;
+    Some(match &s[split..] {
+        "s" => Offset::Duration(Duration::try_seconds(n)?),
+        "min" => Offset::Duration(Duration::try_minutes(n)?),
+        "h" => Offset::Duration(Duration::try_hours(n)?),
+        "d" => Offset::Duration(Duration::try_days(n)?),
+        "w" => Offset::Duration(Duration::try_weeks(n)?),
+        "mo" => Offset::Months(n),
+        "y" => Offset::Months(n.checked_mul(12)?),
+        _ => return None,
+    })
+}
+
+impl DateConfig {
+    pub fn new(cfg: &mut CommandConfig) -> Result<Self, Issue> {
+        let body = cfg.without_auto_escape(CommandConfig::process_body);
+        // because it is already processed, we don't need tools::split_args here
+        let mut spl = body
+            .split_not_escaped::<Vec<_>>(':', '\\', false)
+            .into_iter();
+
+        let mut res = Self {
+            format: spl.next().unwrap(),
+            tz: Tz::Utc,
+            offsets: vec![],
+            parse: None,
+            parse_format: None,
+        };
+        if res.format.is_empty() {
+            res.format = "%+".to_string();
+        }
+
+        for arg in spl {
+            let mut spl = arg.splitn(2, ' ');
+            let verb = spl.next().unwrap();
+            let object = spl.next().unwrap_or_default();
+            match verb {
+                "tz" => {
+                    res.tz = match object {
+                        "utc" | "UTC" => Tz::Utc,
+                        "local" => Tz::Local,
+                        s => return Err(cfg.invalid_args(format!("unknown time zone: {}", s))),
+                    }
+                }
+                "add" | "sub" => {
+                    let off = parse_offset(object)
+                        .ok_or_else(|| cfg.invalid_args(format!("invalid duration: {}", object)))?;
+                    let off = match (verb, off) {
+                        ("add", off) => off,
+                        (_, Offset::Duration(d)) => Offset::Duration(-d),
+                        (_, Offset::Months(n)) => Offset::Months(-n),
+                    };
+                    res.offsets.push(off);
+                }
+                "parse" => res.parse = Some(object.to_string()),
+                "parse_format" => res.parse_format = Some(object.to_string()),
+                verb => {
+                    cfg.issues.push(Issue {
+                        id: "command:invalid_args:partial",
+                        msg: format!("warning: ignoring unrecognised verb `{}`", verb),
+                        span: cfg.cmd_span,
+                    });
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn parse_time(&self, s: &str) -> Option<DateTime<Utc>> {
+        if let Some(secs) = s.strip_prefix('@') {
+            return Utc.timestamp_opt(secs.parse().ok()?, 0).single();
+        }
+        let naive = match &self.parse_format {
+            None => {
+                return DateTime::parse_from_rfc3339(s)
+                    .ok()
+                    .map(|t| t.with_timezone(&Utc))
+            }
+            Some(f) => NaiveDateTime::parse_from_str(s, f)
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(s, f).map(|d| d.and_time(Default::default()))
+                })
+                .ok()?,
+        };
+        match self.tz {
+            Tz::Utc => Some(Utc.from_utc_datetime(&naive)),
+            Tz::Local => Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc)),
+        }
+    }
+}
+
+/// (requires the `chrono` feature) formats a date and time (using the [`chrono`](https://docs.rs/chrono) crate)
+/// - arguments: separated by colons
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+///     - first argument: a [strftime-style format](https://docs.rs/chrono/0.4/chrono/format/strftime/index.html)
+///         - if empty, the time is formatted according to RFC 3339
+///     - remaining arguments: each has the form `<verb> <object>`. Possible verbs are:
+///         - `tz`: the object is either `utc` (the default) or `local`
+///         - `add`, `sub`: the object is an integer followed by a unit,
+///           which is one of `s`, `min`, `h`, `d`, `w`, `mo` or `y`. The time is shifted by that amount
+///         - `parse`: the object is a timestamp that is used instead of the current time,
+///           either `@` followed by a unix timestamp, or in RFC 3339 format
+///         - `parse_format`: a strftime-style format that `parse` uses instead of RFC 3339.
+///           The parsed time is interpreted as being in the time zone set by `tz`
+/// - the current time is taken from the engine's [`Clock`](../struct.Clock.html),
+///   which respects `SOURCE_DATE_EPOCH`
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+pub fn handler(mut cfg: CommandConfig) -> String {
+    let config = match DateConfig::new(&mut cfg) {
+        Ok(x) => x,
+        Err(e) => {
+            cfg.issues.push(e);
+            return String::new();
+        }
+    };
+
+    let mut time = match &config.parse {
+        Some(s) => match config.parse_time(s) {
+            Some(t) => t,
+            None => {
+                cfg.push_invalid_args(format!("invalid timestamp: {}", s));
+                return String::new();
+            }
+        },
+        None => DateTime::<Utc>::from(cfg.engine.clock.now(cfg.engine.sandbox.allow_env)),
+    };
+
+    for off in &config.offsets {
+        let shifted = match *off {
+            Offset::Duration(d) => time.checked_add_signed(d),
+            Offset::Months(n) => u32::try_from(n.unsigned_abs())
+                .ok()
+                .map(Months::new)
+                .and_then(|m| {
+                    if n >= 0 {
+                        time.checked_add_months(m)
+                    } else {
+                        time.checked_sub_months(m)
+                    }
+                }),
+        };
+        time = match shifted {
+            Some(t) => t,
+            None => {
+                cfg.push_invalid_args("date out of range".to_string());
+                return String::new();
+            }
+        };
+    }
+
+    let mut res = String::new();
+    let written = match config.tz {
+        Tz::Utc => write!(res, "{}", time.format(&config.format)),
+        Tz::Local => write!(res, "{}", time.with_timezone(&Local).format(&config.format)),
+    };
+    if written.is_err() {
+        cfg.push_invalid_args(format!("invalid date format: {}", config.format));
+        return String::new();
+    }
+    res
+}
@@ -0,0 +1,83 @@
+use super::tools;
+use crate::{CommandConfig, Issue};
+
+/// Pushes an issue with the id `id` and the message, which starts with the name if one is given
+fn report_impl(mut cfg: CommandConfig, id: &'static str) -> String {
+    // split before processing, so that colons in the output of nested commands are part of the message
+    let mut args = tools::splitn_args(2, cfg.body.clone()).into_iter();
+    let first = args.next().unwrap();
+    // issues aren't output, so their names and messages aren't escaped
+    let (name, msg) = cfg.without_auto_escape(|cfg| match args.next() {
+        Some(msg) => (
+            Some(cfg.process(first).trim().to_string()),
+            cfg.process(msg),
+        ),
+        None => (None, cfg.process(first)),
+    });
+    let msg = match name {
+        Some(name) if name.is_empty() || name.contains(char::is_whitespace) => {
+            cfg.push_invalid_args(format!("invalid issue name: `{}`", name));
+            return String::new();
+        }
+        Some(name) => format!("{}: {}", name, msg.trim()),
+        None => msg.trim().to_string(),
+    };
+    cfg.issues.push(Issue {
+        id,
+        msg,
+        span: cfg.cmd_span,
+    });
+    String::new()
+}
+
+/// reports a warning
+/// - arguments: optionally a name and then a message, separated by a colon
+///   (using [`splitn_args`](tools/fn.splitn_args.html))
+///     - the name may not contain whitespace
+/// - pushes an issue with id `template:warning` and the message, spanning the command
+///     - with a name, the message is `<name>: <message>`
+/// - outputs nothing
+/// - calls `engine.process` on the name and the message after splitting them, without the engine's auto-escaper
+#[inline]
+pub fn warn_handler(cfg: CommandConfig) -> String {
+    report_impl(cfg, "template:warning")
+}
+
+/// reports an error
+/// - the same as [`warn_handler`](fn.warn_handler.html), but the issue's id is `template:error`
+#[inline]
+pub fn error_handler(cfg: CommandConfig) -> String {
+    report_impl(cfg, "template:error")
+}
+
+/// checks a condition and reports an error if it is false
+/// - arguments: the condition (see [`is_truthy`](tools/fn.is_truthy.html)) and optionally a message, separated by a colon
+///   (using [`splitn_args`](tools/fn.splitn_args.html))
+/// - if the condition is false, pushes an issue with id `template:assert` and the message, spanning the command
+/// - outputs nothing
+/// - calls `engine.process` on the condition before doing anything and on the message only if the condition is false,
+///   both without the engine's auto-escaper
+pub fn assert_handler(mut cfg: CommandConfig) -> String {
+    let mut args = tools::splitn_args(2, cfg.body.clone()).into_iter();
+    let cond_src = args.next().unwrap();
+    if cond_src.trim().is_empty() {
+        cfg.push_missing_args("no condition given");
+        return String::new();
+    }
+    if tools::is_truthy(&cfg.without_auto_escape(|cfg| cfg.process(cond_src.clone()))) {
+        return String::new();
+    }
+    let msg = match args.next() {
+        Some(msg) => cfg
+            .without_auto_escape(|cfg| cfg.process(msg))
+            .trim()
+            .to_string(),
+        None => format!("assertion failed: {}", cond_src.trim()),
+    };
+    cfg.issues.push(Issue {
+        id: "template:assert",
+        msg,
+        span: cfg.cmd_span,
+    });
+    String::new()
+}
@@ -0,0 +1,56 @@
+use crate::{CommandConfig, Escaper};
+
+#[inline]
+fn escape_impl(mut cfg: CommandConfig, escaper: Escaper) -> String {
+    // the argument is escaped here, so it must not be escaped by the engine's auto-escaper as well
+    escaper.escape(&cfg.without_auto_escape(CommandConfig::process_body))
+}
+
+/// escapes its argument for use in HTML (see [`Escaper::Html`](../enum.Escaper.html#variant.Html))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn html_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::Html)
+}
+
+/// escapes its argument for use in XML (see [`Escaper::Xml`](../enum.Escaper.html#variant.Xml))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn xml_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::Xml)
+}
+
+/// escapes its argument for use inside a JSON string (see [`Escaper::JsonStr`](../enum.Escaper.html#variant.JsonStr))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn json_str_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::JsonStr)
+}
+
+/// percent-encodes its argument for use in a URL (see [`Escaper::Url`](../enum.Escaper.html#variant.Url))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn url_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::Url)
+}
+
+/// quotes its argument as a single shell argument (see [`Escaper::ShellQuote`](../enum.Escaper.html#variant.ShellQuote))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn shell_quote_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::ShellQuote)
+}
+
+/// escapes its argument for use inside a C string (see [`Escaper::CStr`](../enum.Escaper.html#variant.CStr))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn c_str_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::CStr)
+}
+
+/// escapes its argument for use inside a Rust string (see [`Escaper::RustStr`](../enum.Escaper.html#variant.RustStr))
+/// - calls `engine.process` on its argument before doing anything, without the engine's auto-escaper
+#[inline]
+pub fn rust_str_handler(cfg: CommandConfig) -> String {
+    escape_impl(cfg, Escaper::RustStr)
+}
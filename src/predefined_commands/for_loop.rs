@@ -1,28 +1,100 @@
+use super::load::{parse_delimiter, read_data};
 use super::tools;
-use crate::Issue;
-use crate::{CommandConfig, Span};
+use crate::data::DataFormat;
+use crate::{CommandConfig, CsvOptions, DataValue, Span};
+use crate::{Issue, LoopControl};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use tlib::iter_tools::SplitNotEscapedString;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum ForConfig {
     List(Vec<String>),
-    Range(i128, i128),
+    /// The start, the step and the number of iterations
+    Range(i128, i128, usize),
+    Of(String),
+    Data(DataValue),
+}
+
+/// The options given after the body
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(super) struct ForOptions {
+    sep: String,
+    else_: Option<String>,
+}
+
+impl ForOptions {
+    pub(super) fn new(cfg: &mut CommandConfig, args: impl Iterator<Item = String>) -> Self {
+        let mut res = Self::default();
+        for arg in args {
+            let mut spl = arg.splitn(2, ' ');
+            let verb = spl.next().unwrap();
+            let object = spl.next().unwrap_or_default().to_string();
+            match verb {
+                "sep" => res.sep = cfg.process(object),
+                "else" => res.else_ = Some(object),
+                verb => {
+                    cfg.issues.push(Issue {
+                        id: "command:invalid_args:partial",
+                        msg: format!("warning: ignoring unrecognised verb `{}`", verb),
+                        span: cfg.cmd_span,
+                    });
+                }
+            }
+        }
+        res
+    }
+
+    /// Joins the outputs of the iterations, or outputs the `else` value if there were none
+    pub(super) fn finish(self, cfg: &mut CommandConfig, res: Vec<String>) -> String {
+        match self.else_ {
+            Some(s) if res.is_empty() => cfg.process(s),
+            _ => res.join(&self.sep),
+        }
+    }
+}
+
+/// Parses an integer bound of a range, processing it first
+fn parse_int(cfg: &mut CommandConfig, s: Option<&str>, what: &str) -> Result<i128, Issue> {
+    match s.map(|s| cfg.process(s.to_string())) {
+        Some(s) => s
+            .parse::<i128>()
+            .map_err(|_| cfg.invalid_args(format!("invalid {} integer: {}", what, s))),
+        None => Err(cfg.invalid_args(format!("no {} integer given", what))),
+    }
 }
 
 impl ForConfig {
-    pub fn new(cfg: &mut CommandConfig) -> Result<(String, Self, String), Issue> {
-        let ((head, len), (body, _)) = {
+    pub fn new(cfg: &mut CommandConfig) -> Result<(String, Self, String, ForOptions), Issue> {
+        let ((head, len), body, rest) = {
             // let mut spl = cfg
             //     .body
             //     .split_not_escaped::<Vec<_>>(';', '\\', false)
             //     .into_iter();
             let mut spl = tools::split_args_with_len(cfg.body.clone()).into_iter();
-            (spl.next().unwrap(), spl.next().unwrap_or_default())
+            (
+                spl.next().unwrap(),
+                spl.next().map(|(s, _)| s).unwrap_or_default(),
+                spl.map(|(s, _)| s).collect::<Vec<_>>(),
+            )
         };
+        let options = ForOptions::new(cfg, rest.into_iter());
+        // the elements are stored in the loop variable, so they are escaped when it is substituted
+        let (loopvar, config) = cfg.without_auto_escape(|cfg| Self::parse_head(cfg, head, len))?;
+        Ok((loopvar, config, body, options))
+    }
+
+    /// Processes and parses the loop variable and method
+    fn parse_head(
+        cfg: &mut CommandConfig,
+        head: String,
+        len: usize,
+    ) -> Result<(String, Self), Issue> {
         let subspan = Span::new(0, len);
         let head = cfg.process_subbody(head, subspan).unwrap();
 
-        let mut spl = head.split(' ');
+        let words = head_words(&head);
+        let mut spl = words.iter().map(String::as_str);
         let loopvar = spl
             .next()
             .ok_or_else(|| cfg.invalid_args("no loop variable given".to_string()))?
@@ -78,43 +150,73 @@ impl ForConfig {
             //     Ok((loopvar, ForConfig::List(v, Some(key), desc), body))
             // }
             Some("in") => {
+                let rest = spl.collect::<Vec<_>>();
+                if rest.first() == Some(&"csv") {
+                    let path = rest
+                        .get(1)
+                        .ok_or_else(|| cfg.missing_args("no csv file given"))?;
+                    let mut csv_options = CsvOptions::default();
+                    let mut opts = rest[2..].iter();
+                    while let Some(&opt) = opts.next() {
+                        match opt {
+                            "no_header" => csv_options.has_header = false,
+                            "delimiter" => {
+                                csv_options.delimiter = opts
+                                    .next()
+                                    .and_then(|s| parse_delimiter(s))
+                                    .ok_or_else(|| {
+                                        cfg.invalid_args("invalid or missing delimiter".to_string())
+                                    })?
+                            }
+                            s => return Err(cfg.invalid_args(format!("unknown csv option: {}", s))),
+                        }
+                    }
+                    let data = read_data(cfg, path, DataFormat::Csv(csv_options))?;
+                    return Ok((loopvar, ForConfig::Data(data)));
+                }
+                let arg = rest.join(" ").trim_start().to_string();
+                let arg = cfg.process(arg);
+                let list = if arg.is_empty() {
+                    vec![]
+                } else {
+                    arg.split_not_escaped(':', '\\', false)
+                };
+                Ok((loopvar, ForConfig::List(list)))
+            }
+            Some("of") => {
                 let arg = spl.collect::<Vec<_>>().join(" ").trim_start().to_string();
                 let arg = cfg.process(arg);
-                Ok((
-                    loopvar,
-                    ForConfig::List(arg.split_not_escaped(':', '\\', false)),
-                    body,
-                ))
+                Ok((loopvar, ForConfig::Of(arg)))
             }
             Some("from") => {
-                let from_opt = spl
-                    .next()
-                    .map(|s| cfg.process(s.to_string()))
-                    .map(|s| s.parse::<i128>().map_err(|_| s));
-                let from = match from_opt {
-                    Some(Ok(x)) => x,
-                    Some(Err(s)) => {
-                        return Err(cfg.invalid_args(format!("invalid starting integer: {}", s)))
-                    }
-                    None => return Err(cfg.invalid_args("no starting integer given".to_string())),
-                };
+                let from = parse_int(cfg, spl.next(), "starting")?;
                 match spl.next() {
                     Some("to") => (),
                     Some(s) => return Err(cfg.invalid_args(format!("invalid range end: {}", s))),
                     None => return Err(cfg.invalid_args("no range end given".to_string())),
                 }
-                let to_opt = spl
-                    .next()
-                    .map(|s| cfg.process(s.to_string()))
-                    .map(|s| s.parse::<i128>().map_err(|_| s));
-                let to = match to_opt {
-                    Some(Ok(x)) => x,
-                    Some(Err(s)) => {
-                        return Err(cfg.invalid_args(format!("invalid ending integer: {}", s)))
-                    }
-                    None => return Err(cfg.invalid_args("no ending integer given".to_string())),
+                let to = parse_int(cfg, spl.next(), "ending")?;
+                let step = match spl.next() {
+                    Some("step") => match parse_int(cfg, spl.next(), "step")? {
+                        0 => return Err(cfg.invalid_args("the step may not be 0".to_string())),
+                        x => x.checked_abs().ok_or_else(|| {
+                            cfg.invalid_args(format!("the step is too large: {}", x))
+                        })?,
+                    },
+                    Some(s) => return Err(cfg.invalid_args(format!("invalid range option: {}", s))),
+                    None => 1,
                 };
-                Ok((loopvar, ForConfig::Range(from, to), body))
+                // ranges count downwards if the end is smaller than the start
+                let step = if from > to { -step } else { step };
+                let len = to
+                    .checked_sub(from)
+                    .and_then(|d| d.checked_div(step))
+                    .and_then(|n| usize::try_from(n).ok())
+                    .and_then(|n| n.checked_add(1))
+                    .ok_or_else(|| {
+                        cfg.invalid_args(format!("the range from {} to {} is too large", from, to))
+                    })?;
+                Ok((loopvar, ForConfig::Range(from, step, len)))
             }
             Some(x) => Err(cfg.invalid_args(format!("unknown repeat kind: {}", x))),
             None => Err(cfg.missing_args("no repeat kind given")),
@@ -122,23 +224,170 @@ impl ForConfig {
     }
 }
 
+/// Splits the head of a for-loop into its words, keeping escaped spaces and the escapes
+fn head_words(head: &str) -> Vec<String> {
+    head.split_not_escaped(' ', '\\', true)
+}
+
+/// Checks the loop variable and method of a for-loop without processing them,
+/// i.e. `head` must not contain commands
+///
+/// Returns the message of the issue processing them would cause
+pub(crate) fn check_head(head: &str) -> Result<(), String> {
+    let is_int = |s: Option<&str>| matches!(s.map(str::parse::<i128>), Some(Ok(_)));
+    let words = head_words(head);
+    let mut spl = words.iter().map(String::as_str);
+    if spl.next().unwrap_or_default().is_empty() {
+        return Err("no loop variable given".to_string());
+    }
+    match spl.next() {
+        Some("in") => {
+            let rest = spl.collect::<Vec<_>>();
+            if rest.first() != Some(&"csv") {
+                return Ok(());
+            }
+            if rest.len() < 2 {
+                return Err("no csv file given".to_string());
+            }
+            let mut opts = rest[2..].iter();
+            while let Some(&opt) = opts.next() {
+                match opt {
+                    "no_header" => (),
+                    "delimiter" if opts.next().and_then(|s| parse_delimiter(s)).is_some() => (),
+                    "delimiter" => return Err("invalid or missing delimiter".to_string()),
+                    s => return Err(format!("unknown csv option: {}", s)),
+                }
+            }
+            Ok(())
+        }
+        Some("of") => Ok(()),
+        Some("from") => {
+            if !is_int(spl.next()) {
+                return Err("invalid starting integer".to_string());
+            }
+            match spl.next() {
+                Some("to") => (),
+                Some(s) => return Err(format!("invalid range end: {}", s)),
+                None => return Err("no range end given".to_string()),
+            }
+            if !is_int(spl.next()) {
+                return Err("invalid ending integer".to_string());
+            }
+            match spl.next() {
+                Some("step") => match spl.next().map(str::parse::<i128>) {
+                    Some(Ok(0)) => Err("the step may not be 0".to_string()),
+                    Some(Ok(_)) => Ok(()),
+                    _ => Err("invalid step integer".to_string()),
+                },
+                Some(s) => Err(format!("invalid range option: {}", s)),
+                None => Ok(()),
+            }
+        }
+        Some(x) => Err(format!("unknown repeat kind: {}", x)),
+        None => Err("no repeat kind given".to_string()),
+    }
+}
+
+const LOOP_VARS: [&str; 4] = ["loop.index", "loop.first", "loop.last", "loop.length"];
+
+/// Sets the `loop.*` variables for the iteration `i` of `len`
+fn set_loop_vars(vars: &mut HashMap<String, String>, i: usize, len: usize) {
+    let flag = |b: bool| if b { "true" } else { "" }.to_string();
+    vars.insert("loop.index".to_string(), i.to_string());
+    vars.insert("loop.first".to_string(), flag(i == 0));
+    vars.insert("loop.last".to_string(), flag(i + 1 == len));
+    vars.insert("loop.length".to_string(), len.to_string());
+}
+
+/// Iterates over the elements `keys` of a tree, binding the loop variable to the element
+/// and its nested values (`tree` holds the paths relative to the tree's root, including the leading `.`)
+fn for_each_element(
+    cfg: &mut CommandConfig,
+    loopvar: &str,
+    keys: Vec<String>,
+    tree: Vec<(String, String)>,
+    body: &str,
+) -> Vec<String> {
+    let mut res = Vec::new();
+    let prev_subtree = remove_subtree(&mut cfg.engine.vars, loopvar);
+    let len = keys.len();
+    for (i, k) in keys.into_iter().enumerate() {
+        let elem = format!(".{}", k);
+        remove_subtree(&mut cfg.engine.vars, loopvar);
+        for (path, val) in &tree {
+            if let Some(rest) = path.strip_prefix(&elem) {
+                if rest.is_empty() || rest.starts_with('.') {
+                    cfg.engine
+                        .vars
+                        .insert(format!("{}{}", loopvar, rest), val.clone());
+                }
+            }
+        }
+        set_loop_vars(&mut cfg.engine.vars, i, len);
+        let (out, signal) = cfg.process_loop_body(body.to_string());
+        res.push(out);
+        if signal == Some(LoopControl::Break) {
+            break;
+        }
+    }
+    remove_subtree(&mut cfg.engine.vars, loopvar);
+    cfg.engine.vars.extend(prev_subtree);
+    res
+}
+
+/// Removes all variables whose name starts with `name` followed by a `.`
+fn remove_subtree(vars: &mut HashMap<String, String>, name: &str) -> Vec<(String, String)> {
+    let prefix = format!("{}.", name);
+    let keys = vars
+        .keys()
+        .filter(|k| k.starts_with(&prefix))
+        .cloned()
+        .collect::<Vec<_>>();
+    keys.into_iter()
+        .filter_map(|k| vars.remove_entry(&k))
+        .collect()
+}
+
 /// a for-loop that repeats its body and updates a loop variable according to the argument
 /// - arguments: a loop variable (any string) and then a loop method, separated by a space (`' '`); finally, a colon (`':'`) and then the body
 ///     - escaping a space with `'\\'` is supported, all other instances of `'\\'` are left unchanged
-///     - there are two loop methods:
-///         - `from _ to _` where the `_` are integers
-///             - if the first number is larger than the second, it does nothing
+///     - there are these loop methods:
+///         - `from _ to _`, optionally followed by `step _`, where the `_` are integers
+///             - if the first number is larger than the second, it counts downwards
+///             - the step is the (absolute) amount by which the loop variable changes each iteration and defaults to 1
 ///             - calls `engine.process` on the `_`s before evaluating
+///             - a range with more iterations than fit in a `usize` is invalid
+///             - if the engine's loop limit was set (see [`Engine::with_loop_limit`](../struct.Engine.html#method.with_loop_limit)),
+///               the loop is aborted after as many iterations, which is an issue
 ///         - `in _` where the `_` is a list (colon-delimeted)
 ///             - when the list is specified manually, there are two options
 ///                 1. Escape the colons using `'\\'` and use `"\\\\"` for a literal backslash
 ///                 2. Use the [`eval`](function.eval_handler.html) command
 ///             - calls `engine.process` on it before evaluating
+///             - an empty string is an empty list
+///         - `of _` where the `_` is the name of a variable holding structured data (see [`DataValue`](../enum.DataValue.html))
+///             - iterates over the elements of the array or object, setting the loop variable to the element
+///               and making its nested values available under `<loop variable>.<path>`
+///             - calls `engine.process` on it before evaluating
+///         - `in csv _` where the `_` is the path to a CSV file (see [`csv_handler`](fn.csv_handler.html)),
+///           optionally followed by `delimiter <char>` and/or `no_header`
+///             - iterates over the records, making the field of the column `<header>` available as `<loop variable>.<header>`
+///               (or `<loop variable>.<index>` without a header)
+///             - note that this means that a list can't start with `csv ` (use [`eval`](fn.eval_handler.html) in that case)
+///     - after the body, there may be more arguments (separated by colons) of the form `<verb> <object>`. Possible verbs are:
+///         - `sep`: the object is put between the outputs of two iterations
+///             - calls `engine.process` on it before evaluating
+///         - `else`: the object is processed and output instead if there are no iterations
 /// - calls `engine.process` on the loop variable before processing
 /// - calls `engine.process` each time with the loop variable added to the variables
 ///     - it overwrites any previous value that name had, but restores it once finished
+///     - the same goes for these variables describing the current iteration:
+///         - `loop.index`: the number of previous iterations
+///         - `loop.first`, `loop.last`: `true` in the first/last iteration and empty otherwise
+///         - `loop.length`: the total number of iterations
+/// - the loop can be ended early with [`break`](fn.break_handler.html) and [`continue`](fn.continue_handler.html)
 pub fn handler(mut cfg: CommandConfig) -> String {
-    let (loopvar, config, body) = match ForConfig::new(&mut cfg) {
+    let (loopvar, config, body, options) = match ForConfig::new(&mut cfg) {
         Ok(x) => x,
         Err(e) => {
             cfg.issues.push(e);
@@ -147,37 +396,89 @@ pub fn handler(mut cfg: CommandConfig) -> String {
     };
 
     let prev_loopvar_val = cfg.engine.vars.remove(&loopvar);
+    let prev_loop_vars = LOOP_VARS
+        .iter()
+        .map(|&k| cfg.engine.vars.remove(k))
+        .collect::<Vec<_>>();
 
     let mut res = Vec::new();
     match config {
-        ForConfig::Range(a, b) => {
-            if a > b {
-                return String::new();
-            }
-            for i in a..=b {
-                cfg.engine.vars.insert(loopvar.clone(), i.to_string());
-                res.push(cfg.process(body.clone()));
+        ForConfig::Range(a, step, len) => {
+            let limit = cfg.range_limit();
+            for i in 0..len {
+                if Some(i) == limit {
+                    cfg.issues.push(Issue {
+                        id: "control_flow:iteration_limit",
+                        msg: format!("aborted `for` loop after {} iterations", i),
+                        span: cfg.cmd_span,
+                    });
+                    break;
+                }
+                if !cfg.spend_iteration() {
+                    break;
+                }
+                let x = a + i as i128 * step;
+                cfg.engine.vars.insert(loopvar.clone(), x.to_string());
+                set_loop_vars(&mut cfg.engine.vars, i, len);
+                let (out, signal) = cfg.process_loop_body(body.clone());
+                res.push(out);
+                if signal == Some(LoopControl::Break) {
+                    break;
+                }
             }
         }
         ForConfig::List(v) => {
-            // if let Some(k) = key {
-            //     v.sort_by_key(|s| {
-            //         cfg.engine.vars.insert(loopvar.clone(), s.clone());
-            //         cfg.process(k.clone())
-            //     });
-            // }
-            // if desc {
-            //     v.reverse();
-            // }
-            for s in &v {
+            for (i, s) in v.iter().enumerate() {
                 cfg.engine.vars.insert(loopvar.clone(), s.clone());
-                res.push(cfg.process(body.clone()));
+                set_loop_vars(&mut cfg.engine.vars, i, v.len());
+                let (out, signal) = cfg.process_loop_body(body.clone());
+                res.push(out);
+                if signal == Some(LoopControl::Break) {
+                    break;
+                }
             }
         }
+        ForConfig::Of(name) => {
+            let keys = match cfg.engine.vars.get(&name) {
+                Some(s) => s.split_not_escaped::<Vec<_>>(':', '\\', false),
+                None => {
+                    cfg.push_invalid_args(format!("unknown variable: {}", name));
+                    vec![]
+                }
+            };
+            // taking a snapshot first allows for things like `for x of x.children`
+            let tree = cfg
+                .engine
+                .vars
+                .iter()
+                .filter_map(|(k, v)| Some((k.strip_prefix(&name)?.to_string(), v.clone())))
+                .collect::<Vec<_>>();
+            res = for_each_element(&mut cfg, &loopvar, keys, tree, &body);
+        }
+        ForConfig::Data(data) => {
+            let keys = match &data {
+                DataValue::Array(v) => (0..v.len()).map(|i| i.to_string()).collect(),
+                DataValue::Object(v) => v.iter().map(|(k, _)| k.clone()).collect(),
+                _ => vec![],
+            };
+            let mut tree = HashMap::new();
+            data.flatten_into("", &mut tree);
+            let tree = tree
+                .into_iter()
+                .map(|(k, v)| (format!(".{}", k), v))
+                .collect();
+            res = for_each_element(&mut cfg, &loopvar, keys, tree, &body);
+        }
+    }
+    for (k, prev) in LOOP_VARS.iter().zip(prev_loop_vars) {
+        match prev {
+            Some(x) => cfg.engine.vars.insert(k.to_string(), x),
+            None => cfg.engine.vars.remove(*k),
+        };
     }
     match prev_loopvar_val {
         Some(x) => cfg.engine.vars.insert(loopvar, x),
         None => cfg.engine.vars.remove(&loopvar),
     };
-    res.join("")
+    options.finish(&mut cfg, res)
 }
@@ -0,0 +1,274 @@
+/// A short description of a predefined command, e.g. for showing it in an editor
+///
+/// The full documentation is on the handler functions
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct CommandInfo {
+    /// The name the command is registered under by [`get_all_handlers`](fn.get_all_handlers.html)
+    pub name: &'static str,
+    /// The syntax of a call, e.g. `(%let <name>=<value>%)`
+    pub usage: &'static str,
+    /// A one-sentence description
+    pub summary: &'static str,
+}
+
+impl CommandInfo {
+    /// The number of `:`-separated arguments a call needs according to the usage
+    ///
+    /// Optional parts in brackets don't count, and `...` makes the argument before it optional
+    pub fn min_args(&self) -> usize {
+        let inner = self
+            .usage
+            .strip_prefix("(%")
+            .and_then(|s| s.strip_prefix(self.name))
+            .and_then(|s| s.strip_suffix("%)"))
+            .unwrap_or_default();
+        let mut required = String::new();
+        let mut depth = 0usize;
+        for c in inner.chars() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth = depth.saturating_sub(1),
+                c if depth == 0 => required.push(c),
+                _ => (),
+            }
+        }
+        let args = required.split(':').map(str::trim).collect::<Vec<_>>();
+        let n = args
+            .iter()
+            .filter(|a| !a.is_empty() && **a != "...")
+            .count();
+        if args.contains(&"...") {
+            n.saturating_sub(1)
+        } else {
+            n
+        }
+    }
+}
+
+const fn info(name: &'static str, usage: &'static str, summary: &'static str) -> CommandInfo {
+    CommandInfo {
+        name,
+        usage,
+        summary,
+    }
+}
+
+/// The descriptions of all predefined commands (regardless of enabled features), sorted by name
+pub const COMMAND_INFO: &[CommandInfo] = &[
+    info(
+        "",
+        "(%<name>%)",
+        "Outputs the value of a variable (if there is no command called `<name>`).",
+    ),
+    info(
+        "alt",
+        "(%alt <a>:<b>:...%)",
+        "Outputs the first of its arguments that is not empty.",
+    ),
+    info(
+        "assert",
+        "(%assert <condition>[:<message>]%)",
+        "Reports an error if the condition is false.",
+    ),
+    info(
+        "break",
+        "(%break [<condition>]%)",
+        "Ends the innermost enclosing loop (if the condition is true).",
+    ),
+    info(
+        "c_str",
+        "(%c_str <text>%)",
+        "Escapes its argument for use inside a C string.",
+    ),
+    info(
+        "continue",
+        "(%continue [<condition>]%)",
+        "Continues with the next iteration of the innermost enclosing loop (if the condition is true).",
+    ),
+    info(
+        "csv",
+        "(%csv <path>[:delimiter <char>][:no_header][:as <name>]%)",
+        "Reads a CSV file into the engine's variables.",
+    ),
+    info(
+        "date",
+        "(%date [<format>][:tz <zone>][:add <n><unit>][:sub <n><unit>][:parse <time>][:parse_format <format>]%)",
+        "Formats the current (or a given) date and time.",
+    ),
+    info(
+        "env",
+        "(%env <name>[:<fallback>]%)",
+        "Reads an environment variable of the process.",
+    ),
+    info(
+        "error",
+        "(%error [<name>:]<message>%)",
+        "Reports an error.",
+    ),
+    info("eval", "(%eval <text>%)", "Processes its argument."),
+    info(
+        "for",
+        "(%for <var> in <list>:<body>[:sep <sep>][:else <else>]%)",
+        "Repeats its body for every element of a list, range (`from <a> to <b> [step <n>]`), \
+         structured variable (`of <name>`) or CSV file (`in csv <path>`).",
+    ),
+    info(
+        "html",
+        "(%html <text>%)",
+        "Escapes its argument for use in HTML.",
+    ),
+    info(
+        "include",
+        "(%include <path>%)",
+        "Inserts the contents of a file without processing them.",
+    ),
+    info(
+        "json_str",
+        "(%json_str <text>%)",
+        "Escapes its argument for use inside a JSON string.",
+    ),
+    info(
+        "let",
+        "(%let <name>=<value>%)",
+        "Sets a variable.",
+    ),
+    info(
+        "lit",
+        "(%lit <text>%)",
+        "Outputs its argument literally, without processing it.",
+    ),
+    info(
+        "load_env",
+        "(%load_env <path>[:<name>]%)",
+        "Loads a `.env` file into the engine's variables.",
+    ),
+    info(
+        "load_ini",
+        "(%load_ini <path>[:<name>]%)",
+        "Loads an INI file into the engine's variables.",
+    ),
+    info(
+        "load_json",
+        "(%load_json <path>[:<name>]%)",
+        "Loads a JSON file into the engine's variables.",
+    ),
+    info(
+        "load_toml",
+        "(%load_toml <path>[:<name>]%)",
+        "Loads a TOML file into the engine's variables.",
+    ),
+    info(
+        "lsdir",
+        "(%lsdir <dir>[:exclude_names <patterns>][:include_only_names <patterns>]%)",
+        "Lists the entries of a directory.",
+    ),
+    info(
+        "match",
+        "(%match <value>:<pattern> => <output>:...%)",
+        "Outputs the output of the first arm whose pattern matches the value.",
+    ),
+    info(
+        "raw",
+        "(%raw <name>%)",
+        "Outputs the value of a variable, never escaping it.",
+    ),
+    info(
+        "re_captures",
+        "(%re_captures <var> <regex>:<text>:<body>%)",
+        "Processes its body for every match of a regular expression, with the groups bound to variables.",
+    ),
+    info(
+        "re_find",
+        "(%re_find <regex>:<text>%)",
+        "Outputs the first match of a regular expression.",
+    ),
+    info(
+        "re_find_all",
+        "(%re_find_all <regex>:<text>%)",
+        "Outputs all matches of a regular expression as a `:`-separated list.",
+    ),
+    info(
+        "re_match",
+        "(%re_match <regex>:<text>%)",
+        "Outputs `true` if a regular expression matches somewhere in a text.",
+    ),
+    info(
+        "re_split",
+        "(%re_split <regex>:<text>%)",
+        "Splits a text at every match of a regular expression.",
+    ),
+    info(
+        "re_sub",
+        "(%re_sub <regex>:<substitution>:<text>%)",
+        "Replaces all matches of a regular expression.",
+    ),
+    info(
+        "run",
+        "(%run <program> <args...>%)",
+        "Runs a process and outputs what it prints.",
+    ),
+    info(
+        "rust_str",
+        "(%rust_str <text>%)",
+        "Escapes its argument for use inside a Rust string.",
+    ),
+    info(
+        "safe",
+        "(%safe <name>%)",
+        "Outputs the value of a variable, never escaping it.",
+    ),
+    info(
+        "shell_quote",
+        "(%shell_quote <text>%)",
+        "Quotes its argument as a single shell argument.",
+    ),
+    info(
+        "sort",
+        "(%sort <order>[/<mode>]:<a>:<b>:...%)",
+        "Sorts a `:`-separated list.",
+    ),
+    info(
+        "sort_by",
+        "(%sort_by <var> <order>[/<mode>] <key>:<list>%)",
+        "Sorts a `:`-separated list by a key.",
+    ),
+    info(
+        "try",
+        "(%try <body>:<fallback>%)",
+        "Outputs a fallback if processing the body caused errors.",
+    ),
+    info(
+        "url",
+        "(%url <text>%)",
+        "Percent-encodes its argument for use in a URL.",
+    ),
+    info(
+        "var",
+        "(%var <name>%)",
+        "Outputs the value of a variable.",
+    ),
+    info(
+        "warn",
+        "(%warn [<name>:]<message>%)",
+        "Reports a warning.",
+    ),
+    info(
+        "while",
+        "(%while <condition>:<body>[:sep <sep>][:else <else>]%)",
+        "Repeats its body as long as a condition is true.",
+    ),
+    info(
+        "xml",
+        "(%xml <text>%)",
+        "Escapes its argument for use in XML.",
+    ),
+];
+
+/// Looks up the description of a predefined command by the name it is registered under
+#[inline]
+pub fn command_info(name: &str) -> Option<&'static CommandInfo> {
+    COMMAND_INFO
+        .binary_search_by(|i| i.name.cmp(name))
+        .ok()
+        .map(|i| &COMMAND_INFO[i])
+}
@@ -0,0 +1,176 @@
+use crate::data::{read_data_file, DataError, DataFormat};
+use crate::util::{make_absolute, SplitNotEscapedString};
+use crate::{CommandConfig, CsvOptions, DataValue, Issue, SideEffect};
+use std::path::{Path, PathBuf};
+
+/// Reads a data file on behalf of a command, respecting the sandbox
+///
+/// In a dry run, nothing is read and the data is an empty array
+pub(super) fn read_data(
+    cfg: &mut CommandConfig,
+    path: &str,
+    format: DataFormat,
+) -> Result<DataValue, Issue> {
+    if !cfg.engine.sandbox.allow_fs {
+        return Err(cfg.forbidden("reading files"));
+    }
+    let resolved = make_absolute(path, cfg.engine.root_path.clone());
+    // a path that can't be resolved is recorded as written, since it must not be read either way
+    let effect = SideEffect::ReadFile(
+        resolved
+            .as_ref()
+            .map_or_else(|_| PathBuf::from(path), PathBuf::clone),
+    );
+    if cfg.dry_run(effect).is_some() {
+        return Ok(DataValue::Array(vec![]));
+    }
+    if let Ok(path) = resolved {
+        cfg.record_access(path);
+    }
+
+    read_data_file(Path::new(path), cfg.engine.root_path.clone(), format).map_err(|e| match e {
+        DataError::Io(_, e) => {
+            Issue::io_error(e, cfg.cmd_span, Some("while trying to read data file"))
+        }
+        e => Issue {
+            id: "data:parse_error",
+            msg: e.to_string(),
+            span: cfg.cmd_span,
+        },
+    })
+}
+
+/// Parses the name of a CSV delimiter, which is either a single character, `tab` or `space`
+pub(super) fn parse_delimiter(s: &str) -> Option<char> {
+    match s {
+        "tab" => Some('\t'),
+        "space" => Some(' '),
+        s => {
+            let mut chars = s.chars();
+            let c = chars.next()?;
+            if chars.next().is_none() {
+                Some(c)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn load_impl(mut cfg: CommandConfig, format: DataFormat) -> String {
+    let body = cfg.without_auto_escape(CommandConfig::process_body);
+    // because it is already processed, we don't need tools::split_args here
+    let mut spl = body
+        .splitn_not_escaped::<Vec<_>>(2, ':', '\\', false)
+        .into_iter();
+    let path = spl.next().unwrap();
+    let prefix = spl.next().unwrap_or_default();
+
+    if path.is_empty() {
+        cfg.push_missing_args("no file given");
+        return String::new();
+    }
+
+    match read_data(&mut cfg, &path, format) {
+        Ok(value) => value.flatten_into(&prefix, &mut cfg.engine.vars),
+        Err(e) => cfg.issues.push(e),
+    }
+    String::new()
+}
+
+/// (requires the `serde_json` feature) loads a JSON file into the engine's variables
+/// - arguments: the path to the file and optionally a name to store the data under, separated by a colon
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+///     - without a name, the top-level keys of the document become variables
+/// - nested values can be accessed by their path, e.g. `(%user.name%)` or `(%items.3.id%)`
+///   (see [`DataValue`](../enum.DataValue.html) for the details)
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+/// - outputs nothing
+#[cfg(feature = "serde_json")]
+#[inline]
+pub fn load_json_handler(cfg: CommandConfig) -> String {
+    load_impl(cfg, DataFormat::Json)
+}
+
+/// (requires the `toml` feature) loads a TOML file into the engine's variables
+/// - works just like [`load_json_handler`](fn.load_json_handler.html)
+#[cfg(feature = "toml")]
+#[inline]
+pub fn load_toml_handler(cfg: CommandConfig) -> String {
+    load_impl(cfg, DataFormat::Toml)
+}
+
+/// loads an INI file into the engine's variables
+/// - works just like [`load_json_handler`](fn.load_json_handler.html)
+/// - sections become objects, so `key` in section `[server]` is accessed as `(%server.key%)`
+#[inline]
+pub fn load_ini_handler(cfg: CommandConfig) -> String {
+    load_impl(cfg, DataFormat::Ini)
+}
+
+/// loads a `.env` file (consisting of `KEY=value` lines) into the engine's variables
+/// - works just like [`load_json_handler`](fn.load_json_handler.html)
+#[inline]
+pub fn load_env_handler(cfg: CommandConfig) -> String {
+    load_impl(cfg, DataFormat::Env)
+}
+
+/// reads a CSV file into the engine's variables
+/// - arguments: separated by colons
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+///     - first argument: the path to the file
+///     - remaining arguments: each has the form `<verb> <object>`. Possible verbs are:
+///         - `delimiter`: the object is the character that separates fields (`,` by default), or `tab` or `space`
+///         - `no_header`: has no object. The first line is a record instead of the column names
+///         - `as`: the object is the name to store the records under. Without it, the records are stored at the top level
+/// - the records are stored as an array (see [`DataValue`](../enum.DataValue.html)),
+///   so with a header the field `name` of the first record is `(%<name>.0.name%)` and otherwise it is `(%<name>.0.0%)`
+///     - to iterate over the records, use `(%for row of <name>:...%)` or `(%for row in csv <path>:...%)`
+///       (see [`for_handler`](fn.for_handler.html))
+/// - fields may be quoted with `"`, in which case they may contain the delimiter, newlines and `""` for a literal `"`
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+/// - outputs nothing
+pub fn csv_handler(mut cfg: CommandConfig) -> String {
+    let body = cfg.without_auto_escape(CommandConfig::process_body);
+    // because it is already processed, we don't need tools::split_args here
+    let mut spl = body
+        .split_not_escaped::<Vec<_>>(':', '\\', false)
+        .into_iter();
+    let path = spl.next().unwrap();
+    if path.is_empty() {
+        cfg.push_missing_args("no file given");
+        return String::new();
+    }
+
+    let mut options = CsvOptions::default();
+    let mut prefix = String::new();
+    for arg in spl {
+        let mut spl = arg.splitn(2, ' ');
+        let verb = spl.next().unwrap();
+        let object = spl.next().unwrap_or_default();
+        match verb {
+            "delimiter" => match parse_delimiter(object) {
+                Some(c) => options.delimiter = c,
+                None => {
+                    cfg.push_invalid_args(format!("invalid delimiter: {}", object));
+                    return String::new();
+                }
+            },
+            "no_header" => options.has_header = false,
+            "as" => prefix = object.to_string(),
+            verb => {
+                cfg.issues.push(Issue {
+                    id: "command:invalid_args:partial",
+                    msg: format!("warning: ignoring unrecognised verb `{}`", verb),
+                    span: cfg.cmd_span,
+                });
+            }
+        }
+    }
+
+    match read_data(&mut cfg, &path, DataFormat::Csv(options)) {
+        Ok(value) => value.flatten_into(&prefix, &mut cfg.engine.vars),
+        Err(e) => cfg.issues.push(e),
+    }
+    String::new()
+}
@@ -1,6 +1,6 @@
 use crate::shell_util::matches_pattern;
 use crate::util::{make_absolute, SplitNotEscapedString};
-use crate::{CommandConfig, Issue};
+use crate::{CommandConfig, Issue, SideEffect};
 use std::path::Path;
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
@@ -14,7 +14,7 @@ impl LsdirConfig {
     pub fn new(cfg: &mut CommandConfig) -> Result<Self, Issue> {
         let mut res = Self::default();
 
-        let body = cfg.process_body();
+        let body = cfg.without_auto_escape(CommandConfig::process_body);
 
         // because it is already processed, we don't need tools::split_args here
         let mut spl = body.split_not_escaped::<Vec<_>>(':', '\\', false);
@@ -82,6 +82,8 @@ impl LsdirConfig {
 ///         - `exclude_names`: the object is a whitespace-separated list of patterns (`'\ '` to escape a whitespace). Files whose names match one of these patterns will not be listed
 ///             - the patterns support character-by-character equality as well as single-star-globs
 ///         - `include_only_names`: the object is a whitespace-separated list of patterns (`'\ '` to escape a whitespace). Only Files whose names match one of these patterns will be listed
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+/// - in a dry run, the directory is not listed and the placeholder is output instead
 pub fn handler(mut cfg: CommandConfig) -> String {
     let config = match LsdirConfig::new(&mut cfg) {
         Ok(x) => x,
@@ -91,6 +93,12 @@ pub fn handler(mut cfg: CommandConfig) -> String {
         }
     };
 
+    if !cfg.engine.sandbox.allow_fs {
+        let issue = cfg.forbidden("listing directories");
+        cfg.issues.push(issue);
+        return String::new();
+    }
+
     let path: &Path = config.path.as_ref();
     let dir = match make_absolute(path, cfg.engine.root_path.clone()) {
         Ok(x) => x,
@@ -104,6 +112,10 @@ pub fn handler(mut cfg: CommandConfig) -> String {
         }
     };
 
+    if let Some(out) = cfg.dry_run(SideEffect::ListDir(dir.clone())) {
+        return out;
+    }
+    cfg.record_access(dir.clone());
     let iter = match std::fs::read_dir(&dir) {
         Ok(x) => x,
         Err(e) => {
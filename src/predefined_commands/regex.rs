@@ -1,7 +1,29 @@
+use super::{join_reescape_colon, tools};
 use crate::util::SplitNotEscapedString;
-use crate::{CommandConfig, Issue};
+use crate::{CommandConfig, Escaper, Issue};
 use regex::Regex;
 
+/// Compiles a regular expression, using the engine's cache of compiled regular expressions
+pub(super) fn compile(cfg: &mut CommandConfig, pat: &str) -> Result<Regex, Issue> {
+    if pat.is_empty() {
+        return Err(cfg.missing_args("empty regular expressions are not supported"));
+    }
+    if let Some(re) = cfg.engine.regex_cache.get(pat) {
+        return Ok(re.clone());
+    }
+    let re =
+        Regex::new(pat).map_err(|e| cfg.invalid_args(format!("error compiling regex: {}", e)))?;
+    cfg.engine.regex_cache.insert(pat.to_string(), re.clone());
+    Ok(re)
+}
+
+fn escape(escaper: Option<Escaper>, s: &str) -> String {
+    match escaper {
+        Some(e) => e.escape(s),
+        None => s.to_string(),
+    }
+}
+
 struct RegexArgs {
     pat: String,
     sub: String,
@@ -11,7 +33,7 @@ struct RegexArgs {
 impl RegexArgs {
     pub fn new(cfg: &mut CommandConfig) -> Result<Self, Issue> {
         let mut spl = cfg
-            .process_body()
+            .without_auto_escape(CommandConfig::process_body)
             // because it is already processed, we don't need tools::split_args here
             .splitn_not_escaped::<Vec<_>>(3, ':', '\\', false)
             .into_iter();
@@ -33,23 +55,28 @@ impl RegexArgs {
 }
 
 fn regex_impl(args: RegexArgs, mut cfg: CommandConfig) -> String {
-    let re = match Regex::new(&args.pat) {
+    let re = match compile(&mut cfg, &args.pat) {
         Ok(x) => x,
         Err(e) => {
-            cfg.push_invalid_args(format!("error compiling regex: {}", e));
+            cfg.issues.push(e);
             return String::new();
         }
     };
 
-    let text = cfg.process(args.text);
-    let rep = re.replace_all(&text, &args.sub[..]);
-    cfg.process(rep.to_string())
+    let out = cfg.without_auto_escape(|cfg| {
+        let text = cfg.process(args.text);
+        let rep = re.replace_all(&text, &args.sub[..]);
+        cfg.process(rep.to_string())
+    });
+    cfg.auto_escape(out)
 }
 
 /// (requires the `regex` feature) handles a regular expression (using the [`regex`](https://docs.rs/regex) crate)
 /// - arguments: the regex, the substitution and the text to substitute into, separated with colons
 ///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
-/// - calls `engine.process` on its argument string before doing anything and also after evaluation
+/// - calls `engine.process` on its argument string before doing anything and also after evaluation,
+///   without the engine's auto-escaper
+///     - the output is escaped with it instead
 #[inline]
 pub fn handler(mut cfg: CommandConfig) -> String {
     let re_args = match RegexArgs::new(&mut cfg) {
@@ -61,3 +88,162 @@ pub fn handler(mut cfg: CommandConfig) -> String {
     };
     regex_impl(re_args, cfg)
 }
+
+/// Parses the arguments `<regex>:<text>` common to most regex commands
+fn pattern_and_text(cfg: &mut CommandConfig) -> Result<(Regex, String), Issue> {
+    let mut spl = cfg
+        .without_auto_escape(CommandConfig::process_body)
+        // because it is already processed, we don't need tools::split_args here
+        .splitn_not_escaped::<Vec<_>>(2, ':', '\\', false)
+        .into_iter();
+
+    let pat = spl.next().unwrap();
+    let text = spl
+        .next()
+        .ok_or_else(|| cfg.invalid_args("no text to search in given".to_string()))?;
+    Ok((compile(cfg, &pat)?, text))
+}
+
+/// Calls `f` with the pattern, the text and the escaper for the parts of the text that are output
+fn with_pattern_and_text(
+    mut cfg: CommandConfig,
+    f: impl FnOnce(&Regex, &str, Option<Escaper>) -> String,
+) -> String {
+    match pattern_and_text(&mut cfg) {
+        Ok((re, text)) => f(&re, &text, cfg.engine.auto_escape),
+        Err(e) => {
+            cfg.issues.push(e);
+            String::new()
+        }
+    }
+}
+
+/// (requires the `regex` feature) tests if a regular expression matches somewhere in a text
+/// - arguments: the regex and the text, separated with a colon
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+/// - outputs `true` if it matches and nothing otherwise, so that it can be used with [`alt`](fn.fallback_handler.html)
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+pub fn match_handler(cfg: CommandConfig) -> String {
+    with_pattern_and_text(cfg, |re, text, _| {
+        if re.is_match(text) {
+            "true".to_string()
+        } else {
+            String::new()
+        }
+    })
+}
+
+/// (requires the `regex` feature) outputs the first match of a regular expression in a text
+/// - arguments: the regex and the text, separated with a colon
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+/// - outputs nothing if there is no match
+///     - the match is escaped with the engine's auto-escaper
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+pub fn find_handler(cfg: CommandConfig) -> String {
+    with_pattern_and_text(cfg, |re, text, escaper| {
+        re.find(text)
+            .map_or(String::new(), |m| escape(escaper, m.as_str()))
+    })
+}
+
+/// (requires the `regex` feature) outputs all (non-overlapping) matches of a regular expression in a text
+/// - arguments: the regex and the text, separated with a colon
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+/// - outputs a `:`-separated list
+///     - the entries are escaped with the engine's auto-escaper
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+pub fn find_all_handler(cfg: CommandConfig) -> String {
+    with_pattern_and_text(cfg, |re, text, escaper| {
+        join_reescape_colon(re.find_iter(text).map(|m| escape(escaper, m.as_str())))
+    })
+}
+
+/// (requires the `regex` feature) splits a text at every match of a regular expression
+/// - arguments: the regex and the text, separated with a colon
+///     - escaping colons with `'\\'` is supported, all other instances of `'\\'` are left unchanged
+/// - outputs a `:`-separated list
+///     - the entries are escaped with the engine's auto-escaper
+/// - calls `engine.process` on its argument string before doing anything, without the engine's auto-escaper
+pub fn split_handler(cfg: CommandConfig) -> String {
+    with_pattern_and_text(cfg, |re, text, escaper| {
+        join_reescape_colon(re.split(text).map(|s| escape(escaper, s)))
+    })
+}
+
+/// (requires the `regex` feature) processes a body for every match of a regular expression in a text,
+/// with the capture groups bound to variables
+/// - arguments: separated by colons (using [`split_args`](tools/split_args.html))
+///     - the first argument follows the syntax `<variable> <regex>`
+///     - the second argument is the text to search in
+///     - the third argument is the body
+/// - for every match, `<variable>` is set to the whole match, `<variable>.<n>` to the `n`th group
+///   and `<variable>.<name>` to the group called `name`
+///     - groups that did not participate in the match are set to the empty string
+///     - it overwrites any previous value these names had, but restores them once finished
+/// - calls `engine.process` on the first two arguments before evaluating (without the engine's auto-escaper)
+///   and on the body once for every match
+pub fn captures_handler(mut cfg: CommandConfig) -> String {
+    // note: the body has access to the groups, thus it can't be processed beforehand
+    let mut args = tools::splitn_args(3, cfg.body.clone()).into_iter();
+    let first_arg = args.next().unwrap();
+    let first_arg = cfg.without_auto_escape(|cfg| cfg.process(first_arg));
+    let mut spl = first_arg.splitn(2, ' ');
+    let var = spl.next().unwrap().to_string();
+    let re = match spl.next() {
+        Some(pat) => compile(&mut cfg, pat),
+        None => Err(cfg.invalid_args("no regex given".to_string())),
+    };
+    let re = match re {
+        Ok(x) => x,
+        Err(e) => {
+            cfg.issues.push(e);
+            return String::new();
+        }
+    };
+    // the groups are escaped when they are substituted
+    let text = match args.next() {
+        Some(s) => cfg.without_auto_escape(|cfg| cfg.process(s)),
+        None => {
+            cfg.push_invalid_args("no text to search in given".to_string());
+            return String::new();
+        }
+    };
+    let body = args.next().unwrap_or_default();
+
+    let mut names = vec![var.clone()];
+    names.extend((0..re.captures_len()).map(|i| format!("{}.{}", var, i)));
+    names.extend(
+        re.capture_names()
+            .flatten()
+            .map(|n| format!("{}.{}", var, n)),
+    );
+    let prev = names
+        .iter()
+        .map(|n| cfg.engine.vars.remove(n))
+        .collect::<Vec<_>>();
+
+    let mut res = vec![];
+    for caps in re.captures_iter(&text) {
+        let get = |m: Option<regex::Match>| m.map_or(String::new(), |m| m.as_str().to_string());
+        cfg.engine.vars.insert(var.clone(), get(caps.get(0)));
+        for i in 0..caps.len() {
+            cfg.engine
+                .vars
+                .insert(format!("{}.{}", var, i), get(caps.get(i)));
+        }
+        for name in re.capture_names().flatten() {
+            cfg.engine
+                .vars
+                .insert(format!("{}.{}", var, name), get(caps.name(name)));
+        }
+        res.push(cfg.process(body.clone()));
+    }
+
+    for (name, val) in names.into_iter().zip(prev) {
+        match val {
+            Some(x) => cfg.engine.vars.insert(name, x),
+            None => cfg.engine.vars.remove(&name),
+        };
+    }
+    res.join("")
+}
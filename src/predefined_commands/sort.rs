@@ -0,0 +1,303 @@
+use super::{join_reescape_colon, tools};
+use crate::util::SplitNotEscapedString;
+use crate::CommandConfig;
+use std::cmp::Ordering;
+
+/// The ways in which two entries can be compared
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum SortMode {
+    /// By unicode code points
+    Lexicographic,
+    /// As decimal numbers, entries that are not numbers come last
+    Numeric,
+    /// Runs of digits are compared by their value, so that `file2` comes before `file10`
+    Natural,
+    /// Lexicographic after converting to lowercase
+    CaseInsensitive,
+    /// As version numbers like `1.10.0-beta.2`
+    Version,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct SortOrder {
+    desc: bool,
+    mode: SortMode,
+}
+
+impl SortOrder {
+    /// Parses `<order>` or `<order>/<mode>`
+    fn parse(s: &str) -> Result<Self, String> {
+        let (order, mode) = match s.find('/') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        let desc = match order {
+            "+" | "asc" | "ascending" | "inc" | "increasing" => false,
+            "-" | "desc" | "descending" | "dec" | "decreasing" => true,
+            _ => return Err(format!("invalid sorting order: {}", order)),
+        };
+        let mode = match mode {
+            "" | "lex" | "lexicographic" => SortMode::Lexicographic,
+            "num" | "numeric" => SortMode::Numeric,
+            "nat" | "natural" => SortMode::Natural,
+            "ci" | "case_insensitive" => SortMode::CaseInsensitive,
+            "ver" | "version" => SortMode::Version,
+            _ => return Err(format!("invalid sorting mode: {}", mode)),
+        };
+        Ok(Self { desc, mode })
+    }
+
+    fn compare(self, a: &str, b: &str) -> Ordering {
+        let ord = match self.mode {
+            SortMode::Lexicographic => a.cmp(b),
+            SortMode::Numeric => compare_numeric(a, b),
+            SortMode::Natural => compare_natural(a, b),
+            SortMode::CaseInsensitive => a.to_lowercase().cmp(&b.to_lowercase()),
+            SortMode::Version => compare_version(a, b),
+        };
+        if self.desc {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    /// Sorts `items` by their keys
+    ///
+    /// The sort is stable, also in descending order
+    fn sort<T>(self, items: &mut [(String, T)]) {
+        items.sort_by(|(a, _), (b, _)| self.compare(a, b));
+    }
+}
+
+fn compare_numeric(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Compares two strings of ascii digits by their value
+fn compare_digits(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Splits a string into runs of ascii digits and runs of other characters
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != first.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (chunk, r) = rest.split_at(end);
+        rest = r;
+        Some(chunk)
+    })
+}
+
+fn compare_natural(a: &str, b: &str) -> Ordering {
+    let mut ca = chunks(a);
+    let mut cb = chunks(b);
+    loop {
+        let ord = match (ca.next(), cb.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let x_num = x.starts_with(|c: char| c.is_ascii_digit());
+                let y_num = y.starts_with(|c: char| c.is_ascii_digit());
+                if x_num && y_num {
+                    compare_digits(x, y)
+                } else {
+                    x.cmp(y)
+                }
+            }
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+/// Compares dot-separated components, where missing components count as `0`
+fn compare_components(a: &str, b: &str) -> Ordering {
+    let mut ca = a.split('.');
+    let mut cb = b.split('.');
+    loop {
+        let ord = match (ca.next(), cb.next()) {
+            (None, None) => return Ordering::Equal,
+            (x, y) => compare_natural(x.unwrap_or("0"), y.unwrap_or("0")),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+fn compare_version(a: &str, b: &str) -> Ordering {
+    fn split(s: &str) -> (&str, Option<&str>) {
+        let s = s.strip_prefix('v').unwrap_or(s);
+        // build metadata does not affect the precedence
+        let s = s.split('+').next().unwrap();
+        match s.find('-') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        }
+    }
+    let (a_rel, a_pre) = split(a);
+    let (b_rel, b_pre) = split(b);
+    compare_components(a_rel, b_rel).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        // a pre-release comes before the release itself
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(x), Some(y)) => compare_components(x, y),
+    })
+}
+
+/// Sorts a `:`-separated list
+/// - calls `engine.process` on the entire argument before doing anything, without the engine's auto-escaper,
+///   so that the entries are compared by their unescaped values
+/// - the first argument specifies the sorting order, optionally followed by a `/` and a sorting mode
+///     - valid orders are: `+`, `asc`, `ascending`, `inc`, `increasing`, `-`, `desc`, `descending`, `dec`, `decreasing`
+///     - valid modes are:
+///         - `lex`, `lexicographic` (the default): compares unicode code points
+///         - `num`, `numeric`: compares decimal numbers, entries that are not numbers come last
+///         - `nat`, `natural`: compares runs of digits by their value, so that `file2` comes before `file10`
+///         - `ci`, `case_insensitive`: compares lowercased entries
+///         - `ver`, `version`: compares version numbers like `1.10.0-beta.2`, where pre-releases
+///           come before their release
+///     - no mode depends on the locale
+/// - the sort is stable: entries that compare equal keep their relative order, also when sorting in descending order
+/// - outputs a `:`-separated list, just sorted
+///     - the entries are escaped with the engine's auto-escaper
+pub fn handler(mut cfg: CommandConfig) -> String {
+    let body = cfg.without_auto_escape(CommandConfig::process_body);
+    let mut args = body.split_not_escaped::<Vec<_>>(':', '\\', false);
+    let first_arg = args.remove(0);
+    let order = match SortOrder::parse(&first_arg) {
+        Ok(x) => x,
+        Err(e) => {
+            cfg.push_invalid_args(e);
+            return join_reescape_colon(args.into_iter().map(|s| cfg.auto_escape(s)));
+        }
+    };
+    let mut items = args.into_iter().map(|s| (s, ())).collect::<Vec<_>>();
+    order.sort(&mut items);
+    join_reescape_colon(items.into_iter().map(|(s, _)| cfg.auto_escape(s)))
+}
+
+/// Checks the first argument of `sort_by` (see [`by_handler`](fn.by_handler.html))
+/// and whether there is a list, like `by_handler` does
+///
+/// Returns the message of the issue the command would cause
+pub(crate) fn check_by_head(head: &str, has_list: bool) -> Result<(), String> {
+    let spl = head.splitn_not_escaped::<Vec<_>>(3, ' ', '\\', false);
+    match spl.get(1) {
+        Some(s) => SortOrder::parse(s)?,
+        None => return Err("expected sorting order, got end of argument".to_string()),
+    };
+    if spl.len() < 3 {
+        return Err("no map expression provided".to_string());
+    }
+    if !has_list {
+        return Err("no list to sort provided".to_string());
+    }
+    Ok(())
+}
+
+/// Sorts a `:`-separated list, according to a key
+/// - arguments: separated by colons (using [`split_args`](tools/split_args.html))
+/// - calls `engine.process` on the entire second argument before doing anything
+///   and on `<expr>` once per entry, both without the engine's auto-escaper
+/// - the first argument follows the syntax `<variable> <order> <expr>`
+///     - `<variable>` is the variable by which the current element can be referenced in `<expr>`
+///     - `<order>` is one of `+`, `asc`, `ascending`, `inc`, `increasing`, `-`, `desc`, `descending`, `dec`, `decreasing`,
+///       optionally followed by a `/` and a sorting mode (see [`sort_handler`](fn.sort_handler.html))
+///     - `<expr>` is the expression by which the entries will be compared
+///         - it is evaluated exactly once per entry
+/// - the sort is stable: entries whose keys compare equal keep their relative order
+/// - outputs a sorted `:`-separated list
+///     - the entries are escaped with the engine's auto-escaper
+pub fn by_handler(mut cfg: CommandConfig) -> String {
+    // note: a part of the first argument has access to the loop variable, thus it can't be processed beforehand
+    let mut args = tools::splitn_args(2, cfg.body.clone()).into_iter();
+    let first_arg = args.next().unwrap();
+    let mut spl = first_arg
+        .splitn_not_escaped::<Vec<_>>(3, ' ', '\\', false)
+        .into_iter();
+    let var = spl.next().unwrap();
+    let order = match spl.next().as_deref() {
+        Some(s) => match SortOrder::parse(s) {
+            Ok(x) => x,
+            Err(e) => {
+                cfg.push_invalid_args(e);
+                return args.next().unwrap_or_default();
+            }
+        },
+        None => {
+            cfg.push_invalid_args("expected sorting order, got end of argument".to_string());
+            return args.next().unwrap_or_default();
+        }
+    };
+    let expr = match spl.next() {
+        Some(x) => x,
+        None => {
+            cfg.push_invalid_args("no map expression provided".to_string());
+            return args.next().unwrap_or_default();
+        }
+    };
+
+    let args = match args.next() {
+        Some(x) => x,
+        None => {
+            cfg.push_invalid_args("no list to sort provided".to_string());
+            return String::new();
+        }
+    };
+    // because it is already processed, we don't need tools::split_args here
+    let args = cfg
+        .without_auto_escape(|cfg| cfg.process(args))
+        .split_not_escaped::<Vec<_>>(':', '\\', false);
+
+    let orig_var = cfg.engine.vars.remove(&var);
+    let mut items = cfg.without_auto_escape(|cfg| {
+        args.into_iter()
+            .map(|s| {
+                cfg.engine.vars.insert(var.clone(), s.clone());
+                (cfg.process(expr.to_string()), s)
+            })
+            .collect::<Vec<_>>()
+    });
+    match orig_var {
+        Some(s) => cfg.engine.vars.insert(var, s),
+        None => cfg.engine.vars.remove(&var),
+    };
+    order.sort(&mut items);
+    join_reescape_colon(items.into_iter().map(|(_, s)| cfg.auto_escape(s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare_natural("file2", "file10"), Ordering::Less);
+        assert_eq!(compare_natural("a02b", "a2c"), Ordering::Less);
+        assert_eq!(compare_numeric("-1.5", "1e1"), Ordering::Less);
+        assert_eq!(compare_numeric("x", "3"), Ordering::Greater);
+        assert_eq!(compare_version("1.2.9", "v1.2.10"), Ordering::Less);
+        assert_eq!(compare_version("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(compare_version("1.0.0-rc.1", "1.0.0"), Ordering::Less);
+        assert_eq!(
+            compare_version("1.0.0-beta.2", "1.0.0-beta.11"),
+            Ordering::Less
+        );
+    }
+}
@@ -1,12 +1,15 @@
+use crate::util::comment_len;
 use std::convert::identity;
 use tlib::iter_tools::{indicator, unescape_all, AutoEscape, Unescape};
 
-fn find_seps(s: &str) -> Vec<usize> {
+/// Finds the colons separating arguments (see [`split_args`](fn.split_args.html))
+pub(crate) fn find_seps(s: &str) -> Vec<usize> {
     let mut lvl = 0usize;
     let mut esc = false;
     let mut res = vec![];
+    let mut skip_until = 0;
     for i in 0..s.len() {
-        if !s.is_char_boundary(i) {
+        if !s.is_char_boundary(i) || i < skip_until {
             continue;
         }
         if esc {
@@ -14,6 +17,12 @@ fn find_seps(s: &str) -> Vec<usize> {
             esc = false;
         } else if s[i..].starts_with('\\') {
             esc = true;
+        } else if s[i..].starts_with("(%#") {
+            // separators in comments don't count
+            match comment_len(&s[i..]) {
+                Some(len) => skip_until = i + len,
+                None => break,
+            }
         } else if s[i..].starts_with("(%") {
             lvl += 1;
         } else if s[i..].starts_with("%)") {
@@ -134,6 +143,14 @@ pub fn splitn_args_with_len(n: usize, s: String) -> Vec<(String, usize)> {
     spl
 }
 
+/// Interprets a processed string as a condition
+///
+/// A string is true unless it is empty (after trimming whitespace), `0` or `false`
+#[inline]
+pub fn is_truthy(s: &str) -> bool {
+    !matches!(s.trim(), "" | "0" | "false")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -160,4 +177,13 @@ mod tests {
         ];
         assert_eq!(splitn_args(3, s), ctrl);
     }
+
+    #[test]
+    fn test_split_args_comment() {
+        let s = "a(%# b:(% %):c".to_string();
+        assert_eq!(
+            split_args(s),
+            vec!["a(%# b:(% %)".to_string(), "c".to_string()]
+        );
+    }
 }
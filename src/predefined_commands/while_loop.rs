@@ -0,0 +1,48 @@
+use super::for_loop::ForOptions;
+use super::tools;
+use crate::{CommandConfig, Issue, LoopControl};
+
+/// a loop that repeats its body as long as a condition is true
+/// - arguments: separated by colons (using [`split_args`](tools/split_args.html))
+///     - the first argument is the condition (see [`is_truthy`](tools/fn.is_truthy.html))
+///     - the second argument is the body
+///     - the remaining arguments are the same as the ones after the body of [`for`](fn.for_handler.html)
+/// - calls `engine.process` on the condition before each iteration (without the engine's auto-escaper)
+///   and on the body in each iteration
+///     - the body can change variables that the condition depends on, e.g. using [`let`](fn.set_var_handler.html)
+/// - the loop can be ended early with [`break`](fn.break_handler.html) and [`continue`](fn.continue_handler.html)
+/// - after as many iterations as the engine's loop limit (see [`Engine::with_loop_limit`](../struct.Engine.html#method.with_loop_limit)),
+///   the loop is aborted, which is an issue
+pub fn handler(mut cfg: CommandConfig) -> String {
+    // note: the condition needs to be processed in every iteration, thus it can't be processed beforehand
+    let mut args = tools::split_args(cfg.body.clone()).into_iter();
+    let cond = args.next().unwrap();
+    if cond.is_empty() {
+        cfg.push_missing_args("no condition given");
+        return String::new();
+    }
+    let body = args.next().unwrap_or_default();
+    let options = ForOptions::new(&mut cfg, args);
+
+    let limit = cfg.loop_limit();
+    let mut res = Vec::new();
+    while tools::is_truthy(&cfg.without_auto_escape(|cfg| cfg.process(cond.clone()))) {
+        if res.len() == limit {
+            cfg.issues.push(Issue {
+                id: "control_flow:iteration_limit",
+                msg: format!("aborted `while` loop after {} iterations", limit),
+                span: cfg.cmd_span,
+            });
+            break;
+        }
+        if !cfg.spend_iteration() {
+            break;
+        }
+        let (out, signal) = cfg.process_loop_body(body.clone());
+        res.push(out);
+        if signal == Some(LoopControl::Break) {
+            break;
+        }
+    }
+    options.finish(&mut cfg, res)
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "ppm-process-cache 1";
+
+/// Everything that identifies a process invocation
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ProcessKey {
+    /// The program that is run
+    pub program: String,
+    /// The arguments passed to the program
+    pub args: Vec<String>,
+    /// The working directory of the process
+    pub cwd: PathBuf,
+    /// A hash of the environment variables the process is started with
+    pub env_hash: u64,
+}
+
+impl ProcessKey {
+    /// Creates a key for a process inheriting the environment of the current process
+    pub fn new(program: String, args: Vec<String>, cwd: PathBuf) -> Self {
+        let mut env = std::env::vars_os()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().into_owned(),
+                    v.to_string_lossy().into_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        env.sort();
+        Self {
+            program,
+            args,
+            cwd,
+            env_hash: hash_env(&env),
+        }
+    }
+}
+
+// FNV-1a, because the hash is persisted and thus needs to be stable across builds
+fn hash_env(env: &[(String, String)]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for (k, v) in env {
+        for b in k.bytes().chain(Some(b'=')).chain(v.bytes()).chain(Some(0)) {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct CacheEntry {
+    created: u64,
+    output: String,
+}
+
+/// A cache for the output of processes spawned by the `run` command
+///
+/// Entries are keyed by [`ProcessKey`](struct.ProcessKey.html) and are invalidated when
+/// - they are older than the maximum age (if one is set)
+/// - the cache file they were loaded from has a different format version
+///
+/// Processes that do not exit successfully are never cached
+#[derive(Default, Debug, Clone)]
+pub struct ProcessCache {
+    entries: HashMap<ProcessKey, CacheEntry>,
+    max_age: Option<Duration>,
+}
+
+#[inline]
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[inline]
+fn is_expired(entry: &CacheEntry, max_age: Option<Duration>, now: u64) -> bool {
+    match max_age {
+        Some(age) => now.saturating_sub(entry.created) > age.as_secs(),
+        None => false,
+    }
+}
+
+impl ProcessCache {
+    /// Creates a new, empty cache whose entries never expire
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum age of an entry, after which it is considered invalid
+    #[inline]
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    #[inline]
+    fn is_valid(&self, entry: &CacheEntry) -> bool {
+        !is_expired(entry, self.max_age, now_secs())
+    }
+
+    /// Looks up the output of a process, if it is cached and still valid
+    #[inline]
+    pub fn get(&self, key: &ProcessKey) -> Option<&str> {
+        self.entries
+            .get(key)
+            .filter(|e| self.is_valid(e))
+            .map(|e| e.output.as_str())
+    }
+
+    /// Stores the output of a process
+    #[inline]
+    pub fn insert(&mut self, key: ProcessKey, output: String) {
+        let created = now_secs();
+        self.entries.insert(key, CacheEntry { created, output });
+    }
+
+    /// The number of entries in the cache (including expired ones that were not yet pruned)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache has no entries
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all expired entries
+    pub fn prune(&mut self) {
+        let max_age = self.max_age;
+        let now = now_secs();
+        self.entries.retain(|_, e| !is_expired(e, max_age, now));
+    }
+
+    /// Removes all entries
+    #[inline]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Creates an empty cache with the same maximum age
+    #[inline]
+    pub(crate) fn empty_like(&self) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_age: self.max_age,
+        }
+    }
+
+    /// Adds the entries of another cache, keeping the newer entry if both have one for a key
+    pub fn merge(&mut self, other: ProcessCache) {
+        for (key, entry) in other.entries {
+            match self.entries.get(&key) {
+                Some(e) if e.created > entry.created => (),
+                _ => {
+                    self.entries.insert(key, entry);
+                }
+            }
+        }
+    }
+
+    /// Loads a cache from a file, dropping expired entries
+    ///
+    /// A missing file or one in an unknown format results in an empty cache
+    pub fn load<P: AsRef<Path>>(path: P, max_age: Option<Duration>) -> std::io::Result<Self> {
+        let mut res = Self {
+            entries: HashMap::new(),
+            max_age,
+        };
+        let file = match std::fs::File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(res),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+        match lines.next() {
+            Some(Ok(ref l)) if l == HEADER => (),
+            Some(Err(e)) => return Err(e),
+            _ => return Ok(res),
+        }
+        for line in lines {
+            if let Some((key, entry)) = parse_line(&line?) {
+                res.entries.insert(key, entry);
+            }
+        }
+        res.prune();
+        Ok(res)
+    }
+
+    /// Writes all valid entries of the cache to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut w = BufWriter::new(std::fs::File::create(path)?);
+        writeln!(w, "{}", HEADER)?;
+        for (key, entry) in self.entries.iter().filter(|(_, e)| self.is_valid(e)) {
+            let mut fields = vec![
+                entry.created.to_string(),
+                key.env_hash.to_string(),
+                escape_field(&key.cwd.to_string_lossy()),
+                escape_field(&key.program),
+                escape_field(&entry.output),
+            ];
+            fields.extend(key.args.iter().map(|s| escape_field(s)));
+            writeln!(w, "{}", fields.join("\t"))?;
+        }
+        w.flush()
+    }
+}
+
+fn escape_field(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '\t' => res.push_str("\\t"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn unescape_field(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match iter.next() {
+            Some('t') => res.push('\t'),
+            Some('n') => res.push('\n'),
+            Some('r') => res.push('\r'),
+            Some(c) => res.push(c),
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
+fn parse_line(line: &str) -> Option<(ProcessKey, CacheEntry)> {
+    let mut fields = line.split('\t');
+    let created = fields.next()?.parse().ok()?;
+    let env_hash = fields.next()?.parse().ok()?;
+    let cwd = PathBuf::from(unescape_field(fields.next()?));
+    let program = unescape_field(fields.next()?);
+    let output = unescape_field(fields.next()?);
+    let args = fields.map(unescape_field).collect();
+    Some((
+        ProcessKey {
+            program,
+            args,
+            cwd,
+            env_hash,
+        },
+        CacheEntry { created, output },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let mut cache = ProcessCache::new();
+        let key = ProcessKey::new(
+            "echo".to_string(),
+            vec!["a\tb".to_string(), "c\\".to_string()],
+            PathBuf::from("/tmp"),
+        );
+        cache.insert(key.clone(), "line 1\nline 2\n".to_string());
+
+        let path = std::env::temp_dir().join(format!("ppm-cache-test-{}", std::process::id()));
+        cache.save(&path).unwrap();
+        let loaded = ProcessCache::load(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.get(&key), Some("line 1\nline 2\n"));
+    }
+}
@@ -0,0 +1,43 @@
+/// Restrictions on what commands may access outside of the engine
+///
+/// The predefined commands respect these restrictions and push an issue with id
+/// `"sandbox:forbidden"` instead of doing something that is not allowed.
+/// By default, everything is allowed
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Sandbox {
+    /// Whether commands may read environment variables
+    pub allow_env: bool,
+    /// Whether commands may spawn processes
+    pub allow_processes: bool,
+    /// Whether commands may access the file system
+    pub allow_fs: bool,
+}
+
+impl Default for Sandbox {
+    #[inline]
+    fn default() -> Self {
+        Self::permissive()
+    }
+}
+
+impl Sandbox {
+    /// A sandbox that allows everything
+    #[inline]
+    pub fn permissive() -> Self {
+        Self {
+            allow_env: true,
+            allow_processes: true,
+            allow_fs: true,
+        }
+    }
+
+    /// A sandbox that allows nothing
+    #[inline]
+    pub fn strict() -> Self {
+        Self {
+            allow_env: false,
+            allow_processes: false,
+            allow_fs: false,
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{trim, util, DryRun, Engine, Free, Issue, Sandbox, Span};
+use tlib::iter_tools::AutoEscape;
+
+/// A command or a comment in a template, see [`SyntaxTree`](struct.SyntaxTree.html)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SyntaxNode {
+    /// A comment, including the `(%#` and `%)`
+    Comment(Span),
+    /// A command
+    Command(CommandNode),
+}
+
+impl SyntaxNode {
+    /// The region of the whole node
+    #[inline]
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxNode::Comment(span) => *span,
+            SyntaxNode::Command(cmd) => cmd.span,
+        }
+    }
+}
+
+/// A command in a template, see [`SyntaxTree`](struct.SyntaxTree.html)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CommandNode {
+    /// The region of the whole command, including the percent-parentheses
+    /// (or up to the end of the template if it is not closed)
+    pub span: Span,
+    /// The name (or, for variables like `(%name%)`, the argument) as written, i.e. with escapes
+    pub name: String,
+    /// The region of the name
+    pub name_span: Span,
+    /// The region of the body, without trim markers
+    pub body_span: Span,
+    /// Whether the command has a closing `%)`
+    pub closed: bool,
+    /// The commands and comments inside the body
+    pub children: Vec<SyntaxNode>,
+}
+
+/// A variable binding made by a command, see [`SyntaxTree::bindings`](struct.SyntaxTree.html#method.bindings)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Binding {
+    /// The name of the variable
+    pub name: String,
+    /// The region of the name
+    pub span: Span,
+    /// The region of the command that makes the binding
+    pub cmd_span: Span,
+}
+
+/// The nesting structure of a template, found without processing it
+///
+/// Only the delimiters are looked at, so the nodes inside e.g. `lit` are found too,
+/// although they are never processed
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct SyntaxTree {
+    /// The top-level nodes, in order
+    pub nodes: Vec<SyntaxNode>,
+    /// Unbalanced delimiters and unterminated comments
+    pub issues: Vec<Issue>,
+}
+
+/// Finds the end of the first whitespace-separated word, skipping escaped characters
+fn word_end(s: &str) -> usize {
+    s.char_indices()
+        .auto_escape(|&(_, c)| c == '\\')
+        .find(|&(esc, (_, c))| !esc && c.is_whitespace())
+        .map_or(s.len(), |(_, (i, _))| i)
+}
+
+fn command_node(
+    src: &str,
+    start: usize,
+    end: usize,
+    closed: bool,
+    children: Vec<SyntaxNode>,
+) -> CommandNode {
+    let inner_end = if closed { end - 2 } else { end };
+    let inner = &src[start + 2..inner_end];
+    let (lead, trail) = trim::find_markers(inner);
+    let content = &inner[lead..inner.len() - trail];
+    let content_start = start + 2 + lead;
+
+    let name_len = word_end(content);
+    let body_start = content[name_len..]
+        .chars()
+        .next()
+        .map_or(name_len, |c| name_len + c.len_utf8());
+    CommandNode {
+        span: Span::new(start, end - start),
+        name: content[..name_len].to_string(),
+        name_span: Span::new(content_start, name_len),
+        body_span: Span::new(content_start + body_start, content.len() - body_start),
+        closed,
+        children,
+    }
+}
+
+impl SyntaxTree {
+    /// Finds all commands and comments in a template
+    pub fn parse(src: &str) -> Self {
+        let mut issues = vec![];
+        // the start of every open command and the nodes found inside it so far
+        let mut frames: Vec<(usize, Vec<SyntaxNode>)> = vec![(0, vec![])];
+
+        let mut iter = src
+            .char_indices()
+            .auto_escape(|&(_, c)| c == '\\')
+            .peekable();
+        while let Some((esc, (i, c))) = iter.next() {
+            match c {
+                '(' if !esc && src[i..].starts_with("(%#") => {
+                    let len = util::comment_len(&src[i..]).unwrap_or_else(|| {
+                        issues.push(Issue {
+                            id: "comment:no_end",
+                            msg: "Comment has no end".to_string(),
+                            span: Span::new(i, src.len() - i),
+                        });
+                        src.len() - i
+                    });
+                    frames
+                        .last_mut()
+                        .unwrap()
+                        .1
+                        .push(SyntaxNode::Comment(Span::new(i, len)));
+                    while iter.next_if(|&(_, (j, _))| j < i + len).is_some() {}
+                }
+                '(' if !esc && iter.next_if(|&(esc, (_, c))| !esc && c == '%').is_some() => {
+                    frames.push((i, vec![]));
+                }
+                '%' if !esc && iter.next_if(|&(esc, (_, c))| !esc && c == ')').is_some() => {
+                    if frames.len() == 1 {
+                        issues.push(Issue {
+                            id: "command:unmatched_closing_delim",
+                            msg: "Unmatched '%)'".to_string(),
+                            span: Span::new(i, 2),
+                        });
+                        continue;
+                    }
+                    let (start, children) = frames.pop().unwrap();
+                    let node = command_node(src, start, i + 2, true, children);
+                    frames.last_mut().unwrap().1.push(SyntaxNode::Command(node));
+                }
+                _ => (),
+            }
+        }
+        while frames.len() > 1 {
+            let (start, children) = frames.pop().unwrap();
+            if frames.len() == 1 {
+                issues.push(Issue {
+                    id: "command:no_end",
+                    msg: "Command has no end".to_string(),
+                    span: Span::new(start, src.len() - start),
+                });
+            }
+            let node = command_node(src, start, src.len(), false, children);
+            frames.last_mut().unwrap().1.push(SyntaxNode::Command(node));
+        }
+
+        Self {
+            nodes: frames.pop().unwrap().1,
+            issues,
+        }
+    }
+
+    /// All commands, outer ones before the ones in their bodies
+    pub fn commands(&self) -> Vec<&CommandNode> {
+        fn visit<'a>(nodes: &'a [SyntaxNode], res: &mut Vec<&'a CommandNode>) {
+            for node in nodes {
+                if let SyntaxNode::Command(cmd) = node {
+                    res.push(cmd);
+                    visit(&cmd.children, res);
+                }
+            }
+        }
+        let mut res = vec![];
+        visit(&self.nodes, &mut res);
+        res
+    }
+
+    /// The innermost command whose span contains the position `pos`
+    pub fn command_at(&self, pos: usize) -> Option<&CommandNode> {
+        self.commands()
+            .into_iter()
+            .rev()
+            .find(|c| c.span.start <= pos && pos < c.span.end().max(c.span.start + 1))
+    }
+
+    /// The variables bound by the predefined commands `let`, `for`, `sort_by` and `re_captures`, in order
+    ///
+    /// Names that are computed by a nested command are skipped
+    pub fn bindings(&self, src: &str) -> Vec<Binding> {
+        self.commands()
+            .into_iter()
+            .filter_map(|cmd| {
+                let (start, name) = binding_name(cmd, src)?;
+                if name.is_empty() || name.contains("(%") {
+                    return None;
+                }
+                Some(Binding {
+                    name: name.to_string(),
+                    span: Span::new(cmd.body_span.start + start, name.len()),
+                    cmd_span: cmd.span,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether one of the commands of [`bindings`](#method.bindings) binds a name that is computed by a nested command
+    pub(crate) fn has_computed_bindings(&self, src: &str) -> bool {
+        self.commands()
+            .into_iter()
+            .filter_map(|cmd| binding_name(cmd, src))
+            .any(|(_, name)| name.contains("(%"))
+    }
+}
+
+/// Finds the (trimmed) name a binding command binds and its offset in the command's body
+fn binding_name<'a>(cmd: &CommandNode, src: &'a str) -> Option<(usize, &'a str)> {
+    let body = cmd.body_span.index_str(src);
+    let len = match &cmd.name[..] {
+        "let" => body.find('=')?,
+        "for" | "sort_by" | "re_captures" => word_end(body),
+        _ => return None,
+    };
+    let name = &body[..len];
+    Some((name.len() - name.trim_start().len(), name.trim()))
+}
+
+/// The number of iterations after which loops are aborted by [`Engine::analyze`](struct.Engine.html#method.analyze)
+const ANALYZE_LOOP_LIMIT: usize = 100;
+
+/// The number of iterations after which all loops together are aborted by [`Engine::analyze`](struct.Engine.html#method.analyze),
+/// so that nested loops don't multiply the limit
+const ANALYZE_LOOP_BUDGET: usize = 1_000;
+
+impl Engine<Free> {
+    /// Finds the issues of a template without side effects, e.g. for showing them in an editor
+    ///
+    /// The template is processed by a copy of `self` with a strict [`Sandbox`](struct.Sandbox.html),
+    /// in a [dry run](#method.with_dry_run) and without process cache,
+    /// so the issues about the sandbox forbidding something are left out.\
+    /// To keep this fast, loops are aborted after at most 100 iterations
+    /// and all loops together after 1000 iterations, even if they are nested.
+    /// Only loops that would also be aborted by `self` are reported
+    pub fn analyze(&self, src: &str) -> Vec<Issue> {
+        let limit = self
+            .loop_limit
+            .map_or(ANALYZE_LOOP_LIMIT, |l| l.min(ANALYZE_LOOP_LIMIT));
+        let mut en = self
+            .clone()
+            .with_sandbox(Sandbox::strict())
+            .with_dry_run(DryRun::new())
+            .with_loop_limit(limit)
+            .without_process_cache();
+        en.loop_budget = Some(ANALYZE_LOOP_BUDGET);
+        let (_, mut issues) = en.process_new(src.to_string());
+        issues.retain(|i| {
+            i.id != "sandbox:forbidden"
+                && (i.id != "control_flow:iteration_limit" || Some(limit) == self.loop_limit)
+        });
+        issues
+    }
+}
@@ -0,0 +1,312 @@
+use crate::util::glob_matches;
+use crate::{Engine, Free, Issue, RenderJob, Severity, SideEffect, Span};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Selects which files [`Engine::process_tree`](struct.Engine.html#method.process_tree) renders
+/// and what it does with the others
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct TreeOptions {
+    /// Files ending with `.<extension>` are templates, the extension is removed from the output name
+    pub extensions: Vec<String>,
+    /// Files matching one of these glob patterns are templates, their output name is kept
+    ///
+    /// Patterns without a `/` are matched against the file name,
+    /// others against the path relative to the input directory (using `/` as separator)
+    pub globs: Vec<String>,
+    /// Whether files that are not templates are copied to the output directory
+    pub copy_others: bool,
+    /// The number of threads to render with, see [`Engine::process_batch`](struct.Engine.html#method.process_batch)
+    pub threads: usize,
+}
+
+impl TreeOptions {
+    /// Creates options that render nothing and copy all files
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            copy_others: true,
+            ..Self::default()
+        }
+    }
+
+    /// Adds an extension that marks a file as template, e.g. `ppm` for `index.html.ppm`
+    #[inline]
+    pub fn with_extension(mut self, ext: &str) -> Self {
+        self.extensions
+            .push(ext.trim_start_matches('.').to_string());
+        self
+    }
+
+    /// Adds a glob pattern that marks a file as template
+    #[inline]
+    pub fn with_glob(mut self, pat: &str) -> Self {
+        self.globs.push(pat.to_string());
+        self
+    }
+
+    /// Makes the rendering skip files that are not templates instead of copying them
+    #[inline]
+    pub fn without_copying(mut self) -> Self {
+        self.copy_others = false;
+        self
+    }
+
+    /// Sets the number of threads to render with
+    #[inline]
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Returns the output path of a template, or `None` if the path is no template
+    fn output_path(&self, rel: &Path) -> Option<PathBuf> {
+        let name = rel.file_name()?.to_str()?;
+        for ext in &self.extensions {
+            if let Some(stem) = name.strip_suffix(ext.as_str()) {
+                if let Some(stem) = stem.strip_suffix('.').filter(|s| !s.is_empty()) {
+                    return Some(rel.with_file_name(stem));
+                }
+            }
+        }
+        let rel_str = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.globs
+            .iter()
+            .any(|pat| match pat.contains('/') {
+                true => glob_matches(pat, &rel_str),
+                false => glob_matches(pat, name),
+            })
+            .then(|| rel.to_path_buf())
+    }
+}
+
+/// What happened to a single file in [`Engine::process_tree`](struct.Engine.html#method.process_tree)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TreeFile {
+    /// The path of the file, relative to the input directory
+    pub input: PathBuf,
+    /// The path of the result, relative to the output directory
+    pub output: PathBuf,
+    /// The source of the template if the file was rendered, `None` if it was copied
+    pub template: Option<String>,
+    /// The issues encountered while rendering or copying the file
+    ///
+    /// Their spans refer to `template` (IO errors have an empty span)
+    pub issues: Vec<Issue>,
+    /// The files and directories the template read while rendering
+    /// (see [`Engine::accessed_files`](struct.Engine.html#method.accessed_files)), empty for copied files
+    pub dependencies: BTreeSet<PathBuf>,
+    /// In a dry run (see [`Engine::with_dry_run`](struct.Engine.html#method.with_dry_run)),
+    /// the side effects of rendering the template and of writing or copying the file, in order
+    ///
+    /// Empty if the side effects were performed
+    pub side_effects: Vec<SideEffect>,
+}
+
+/// The result of [`Engine::process_tree`](struct.Engine.html#method.process_tree)
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct TreeReport {
+    /// All files that were rendered or copied, sorted by their input path
+    pub files: Vec<TreeFile>,
+}
+
+impl TreeReport {
+    /// Iterates over all issues of all files
+    #[inline]
+    pub fn issues(&self) -> impl Iterator<Item = (&TreeFile, &Issue)> {
+        self.files
+            .iter()
+            .flat_map(|f| f.issues.iter().map(move |i| (f, i)))
+    }
+
+    /// Iterates over all side effects that were recorded for all files in a dry run
+    #[inline]
+    pub fn side_effects(&self) -> impl Iterator<Item = (&TreeFile, &SideEffect)> {
+        self.files
+            .iter()
+            .flat_map(|f| f.side_effects.iter().map(move |e| (f, e)))
+    }
+
+    /// The highest severity of all issues, or `None` if there are none
+    #[inline]
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.issues().map(|(_, i)| i.severity()).max()
+    }
+}
+
+/// Collects all files below `dir` (relative to `base`), sorted, without descending into `skip`
+///
+/// Symbolic links to directories are followed, unless they point to `dir` or one of its ancestors
+/// (whose canonical paths are in `ancestors`), which would recurse forever
+fn walk(
+    base: &Path,
+    dir: &Path,
+    skip: &Path,
+    ancestors: &mut Vec<PathBuf>,
+    res: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(base.join(dir))?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for name in entries {
+        let rel = dir.join(name);
+        let path = base.join(&rel);
+        if !path.is_dir() {
+            res.push(rel);
+            continue;
+        }
+        let canonical = path.canonicalize()?;
+        if canonical != skip && !ancestors.contains(&canonical) {
+            ancestors.push(canonical);
+            walk(base, &rel, skip, ancestors, res)?;
+            ancestors.pop();
+        }
+    }
+    Ok(())
+}
+
+/// Lists all files below `input` (relative to it), sorted, skipping `output` if it is inside `input`
+pub(crate) fn tree_paths(input: &Path, output: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let skip = output.canonicalize().unwrap_or_default();
+    let mut ancestors = vec![input.canonicalize()?];
+    let mut paths = vec![];
+    walk(input, Path::new(""), &skip, &mut ancestors, &mut paths)?;
+    Ok(paths)
+}
+
+impl Engine<Free> {
+    /// Renders a directory tree of templates into a mirrored output directory
+    ///
+    /// Every template (see [`TreeOptions`](struct.TreeOptions.html)) is rendered with its own directory as root path
+    /// and written to the same relative path in `output`, other files are copied (if enabled).
+    /// Missing directories are created, existing files are overwritten.
+    /// If `output` is inside `input`, it is skipped when walking `input`.
+    /// The templates are rendered like by [`process_batch`](#method.process_batch),
+    /// so processes are looked up in the process cache, but not added to it
+    ///
+    /// In a dry run (see [`with_dry_run`](#method.with_dry_run)), the templates are still read and rendered,
+    /// but nothing is written, copied or created. This is recorded in [`TreeFile::side_effects`](struct.TreeFile.html#structfield.side_effects) instead
+    ///
+    /// # Fails
+    /// Only if `input` can't be walked, errors reading or writing single files are reported as
+    /// issues of that file (with the id `io_error`)
+    pub fn process_tree(
+        &self,
+        input: &Path,
+        output: &Path,
+        options: &TreeOptions,
+    ) -> std::io::Result<TreeReport> {
+        let paths = tree_paths(input, output)?;
+        Ok(TreeReport {
+            files: self.render_tree_paths(input, output, options, paths),
+        })
+    }
+
+    /// Renders or copies the given files (relative to `input`), see [`process_tree`](#method.process_tree)
+    pub(crate) fn render_tree_paths(
+        &self,
+        input: &Path,
+        output: &Path,
+        options: &TreeOptions,
+        paths: Vec<PathBuf>,
+    ) -> Vec<TreeFile> {
+        let mut files = vec![];
+        let mut jobs = vec![];
+        for rel in paths {
+            let mut file = TreeFile {
+                output: rel.clone(),
+                input: rel,
+                template: None,
+                issues: vec![],
+                dependencies: BTreeSet::new(),
+                side_effects: vec![],
+            };
+            match options.output_path(&file.input) {
+                Some(out) => {
+                    file.output = out;
+                    match std::fs::read_to_string(input.join(&file.input)) {
+                        Ok(s) => {
+                            let dir = input.join(&file.input).parent().map(Path::to_path_buf);
+                            let mut job = RenderJob::new(s.clone());
+                            job.root_path = dir;
+                            jobs.push((files.len(), job));
+                            file.template = Some(s);
+                        }
+                        Err(e) => {
+                            file.issues
+                                .push(Issue::io_error(e, Span::empty(), Some("reading")))
+                        }
+                    }
+                }
+                None if options.copy_others => (),
+                None => continue,
+            }
+            files.push(file);
+        }
+
+        let (indices, jobs): (Vec<_>, Vec<_>) = jobs.into_iter().unzip();
+        let mut results = indices
+            .into_iter()
+            .zip(
+                self.process_batch_with(jobs, options.threads, Self::process_job_tracked)
+                    .0,
+            )
+            .peekable();
+
+        // the directories a dry run would have created so far
+        let mut created = BTreeSet::<PathBuf>::new();
+        for (i, file) in files.iter_mut().enumerate() {
+            let dest = output.join(&file.output);
+            let result = results.next_if(|(k, _)| *k == i).map(|(_, r)| r);
+            if self.dry_run.is_some() {
+                if let Some(job) = result {
+                    file.issues.extend(job.issues);
+                    file.dependencies = job.accessed_files;
+                    file.side_effects = job.audit_log;
+                }
+                // creating a directory creates its missing parents too
+                let is_created = |p: &Path| p.is_dir() || created.iter().any(|c| c.starts_with(p));
+                if let Some(parent) = dest.parent().filter(|p| !is_created(p)) {
+                    created.insert(parent.to_path_buf());
+                    file.side_effects
+                        .push(SideEffect::CreateDir(parent.to_path_buf()));
+                }
+                match &file.template {
+                    Some(_) => file.side_effects.push(SideEffect::WriteFile(dest)),
+                    None if file.issues.is_empty() => {
+                        file.side_effects.push(SideEffect::CopyFile {
+                            from: input.join(&file.input),
+                            to: dest,
+                        })
+                    }
+                    None => (),
+                }
+                continue;
+            }
+            let written = dest
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| match result {
+                    Some(job) => {
+                        file.issues.extend(job.issues);
+                        file.dependencies = job.accessed_files;
+                        std::fs::write(&dest, job.output)
+                    }
+                    None if file.template.is_none() && file.issues.is_empty() => {
+                        std::fs::copy(input.join(&file.input), &dest).map(|_| ())
+                    }
+                    None => Ok(()),
+                });
+            if let Err(e) = written {
+                file.issues
+                    .push(Issue::io_error(e, Span::empty(), Some("writing")));
+            }
+        }
+        files
+    }
+}
@@ -0,0 +1,134 @@
+/// A part of the output of [`Engine::process`](../struct.Engine.html#method.process),
+/// remembering what kind of atom it came from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct OutputPiece {
+    pub text: String,
+    pub is_command: bool,
+    /// Whether the whitespace at the end of the previous piece should be removed
+    pub trim_before: bool,
+    /// Whether the whitespace at the start of the next piece should be removed
+    pub trim_after: bool,
+}
+
+impl OutputPiece {
+    #[inline]
+    pub fn text(text: String) -> Self {
+        Self {
+            text,
+            is_command: false,
+            trim_before: false,
+            trim_after: false,
+        }
+    }
+}
+
+/// Finds the trim markers of a command (without the percent-parentheses)
+///
+/// Returns the lengths of the leading marker (`-` followed by whitespace) and
+/// the trailing marker (whitespace followed by `-`), including that whitespace,
+/// or `0` if there is no such marker
+pub(crate) fn find_markers(s: &str) -> (usize, usize) {
+    let lead = match s.strip_prefix('-') {
+        Some(rest) if rest.starts_with(char::is_whitespace) => s.len() - rest.trim_start().len(),
+        _ => 0,
+    };
+    let trail = match s[lead..].strip_suffix('-') {
+        Some(rest) if rest.ends_with(char::is_whitespace) => s.len() - lead - rest.trim_end().len(),
+        _ => 0,
+    };
+    (lead, trail)
+}
+
+/// Removes the whitespace next to commands with trim markers
+///
+/// Only text from the template is trimmed, never the output of a command
+pub(crate) fn apply_markers(pieces: &mut [OutputPiece]) {
+    for i in 0..pieces.len() {
+        if !pieces[i].is_command {
+            continue;
+        }
+        if pieces[i].trim_before && i > 0 && !pieces[i - 1].is_command {
+            let len = pieces[i - 1].text.trim_end().len();
+            pieces[i - 1].text.truncate(len);
+        }
+        if pieces[i].trim_after && i + 1 < pieces.len() && !pieces[i + 1].is_command {
+            let text = &mut pieces[i + 1].text;
+            let start = text.len() - text.trim_start().len();
+            text.replace_range(..start, "");
+        }
+    }
+}
+
+#[inline]
+fn is_blank(s: &str) -> bool {
+    s.chars().all(|c| c != '\n' && c.is_whitespace())
+}
+
+/// Removes lines that only consist of whitespace and commands without output,
+/// including the line break at their end
+pub(crate) fn remove_command_lines(pieces: &mut [OutputPiece]) {
+    let is_silent = |p: &OutputPiece| p.is_command && p.text.is_empty();
+    let mut i = 0;
+    while i < pieces.len() {
+        if !is_silent(&pieces[i]) {
+            i += 1;
+            continue;
+        }
+        // the run of silent commands that may be separated by blanks
+        let mut last = i;
+        for (k, p) in pieces.iter().enumerate().skip(i + 1) {
+            if is_silent(p) {
+                last = k;
+            } else if p.is_command || !is_blank(&p.text) {
+                break;
+            }
+        }
+
+        let starts_line = match i.checked_sub(1).map(|k| &pieces[k]) {
+            None => true,
+            Some(p) if p.is_command => false,
+            Some(p) => match p.text.rfind('\n') {
+                Some(nl) => is_blank(&p.text[nl + 1..]),
+                None => i == 1 && is_blank(&p.text),
+            },
+        };
+        let ends_line = match pieces.get(last + 1) {
+            None => true,
+            Some(p) if p.is_command => false,
+            Some(p) => match p.text.find('\n') {
+                Some(nl) => is_blank(&p.text[..nl]),
+                None => last + 2 == pieces.len() && is_blank(&p.text),
+            },
+        };
+
+        if starts_line && ends_line {
+            if i > 0 {
+                let text = &mut pieces[i - 1].text;
+                let len = text.rfind('\n').map_or(0, |nl| nl + 1);
+                text.truncate(len);
+            }
+            for p in &mut pieces[i..=last] {
+                p.text.clear();
+            }
+            if let Some(p) = pieces.get_mut(last + 1) {
+                let end = p.text.find('\n').map_or(p.text.len(), |nl| nl + 1);
+                p.text.replace_range(..end, "");
+            }
+        }
+        i = last + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_markers() {
+        assert_eq!(find_markers("- x -"), (2, 2));
+        assert_eq!(find_markers("-\n\tx"), (3, 0));
+        assert_eq!(find_markers("let x=-"), (0, 0));
+        assert_eq!(find_markers("-x"), (0, 0));
+        assert_eq!(find_markers("x  -"), (0, 3));
+    }
+}
@@ -217,3 +217,85 @@ pub fn make_absolute<P: AsRef<Path>>(p: P, dir: Option<PathBuf>) -> std::io::Res
         }
     }
 }
+
+/// Finds the end of a comment, given a string starting with `(%#`
+///
+/// Returns the length of the comment including the closing `%)`, which is the first unescaped one
+#[inline]
+pub(crate) fn comment_len(s: &str) -> Option<usize> {
+    let mut esc = false;
+    for (i, c) in s.char_indices().skip(3) {
+        if esc {
+            esc = false;
+        } else if c == '\\' {
+            esc = true;
+        } else if s[i..].starts_with("%)") {
+            return Some(i + 2);
+        }
+    }
+    None
+}
+
+/// Tests if `s` matches a glob pattern, where `*` matches any sequence of characters and `?` any single character
+///
+/// The wildcards can be escaped with `'\\'`
+pub(crate) fn glob_matches(pat: &str, s: &str) -> bool {
+    // (is_wildcard, char)
+    let mut p = vec![];
+    let mut iter = pat.chars().peekable();
+    while let Some(c) = iter.next() {
+        match (c, iter.peek()) {
+            ('\\', Some(&n)) if "*?\\".contains(n) => {
+                p.push((false, n));
+                iter.next();
+            }
+            (c, _) => p.push((c == '*' || c == '?', c)),
+        }
+    }
+    let s = s.chars().collect::<Vec<_>>();
+
+    // classic backtracking to the last `*`
+    let (mut pi, mut si) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        match p.get(pi) {
+            Some(&(true, '*')) => {
+                star = Some((pi, si));
+                pi += 1;
+            }
+            Some(&(true, '?')) => {
+                pi += 1;
+                si += 1;
+            }
+            Some(&(_, c)) if c == s[si] => {
+                pi += 1;
+                si += 1;
+            }
+            _ => match star {
+                Some((spi, ssi)) => {
+                    pi = spi + 1;
+                    si = ssi + 1;
+                    star = Some((spi, ssi + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&t| t == (true, '*'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_matches("*.rs", "main.rs"));
+        assert!(glob_matches("a*b*c", "aXbYbc"));
+        assert!(glob_matches("?ü*", "äü"));
+        assert!(!glob_matches("ab", "axb"));
+        assert!(!glob_matches("*.rs", "main.rsx"));
+        assert!(glob_matches("a\\*", "a*"));
+        assert!(!glob_matches("a\\*", "ab"));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::tree::tree_paths;
+use crate::{Engine, Free, TreeOptions, TreeReport};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[inline]
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Detects changes to files by polling their modification times
+///
+/// This works on every platform, but only notices changes when [`poll`](#method.poll) is called.
+/// Files that don't exist can be watched too, creating them counts as a change
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct FileWatcher {
+    /// The last seen modification time, or `None` if the file didn't exist (or it couldn't be read)
+    mtimes: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl FileWatcher {
+    /// Creates a watcher that watches nothing
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching a file or directory, remembering its current modification time
+    ///
+    /// Paths that are already watched keep the time they had,
+    /// so that changes since then are still reported
+    #[inline]
+    pub fn watch(&mut self, path: PathBuf) {
+        self.mtimes.entry(path).or_insert_with_key(|p| modified(p));
+    }
+
+    /// Starts watching a file or directory like [`watch`](#method.watch),
+    /// but as if that had happened at `since`
+    ///
+    /// This is for files that are only known to be relevant after reading them, e.g. while rendering.
+    /// If such a file was modified after `since`, the next [`poll`](#method.poll) reports it
+    pub fn watch_since(&mut self, path: PathBuf, since: SystemTime) {
+        self.mtimes
+            .entry(path)
+            .or_insert_with_key(|p| match modified(p) {
+                // the modification time only has to differ from the current one to be reported
+                Some(t) if t >= since => Some(since),
+                x => x,
+            });
+    }
+
+    /// Stops watching a file or directory
+    #[inline]
+    pub fn unwatch(&mut self, path: &Path) {
+        self.mtimes.remove(path);
+    }
+
+    /// Stops watching every path for which `f` returns `false`
+    #[inline]
+    pub fn retain(&mut self, mut f: impl FnMut(&Path) -> bool) {
+        self.mtimes.retain(|p, _| f(p));
+    }
+
+    /// Whether a path is watched
+    #[inline]
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.mtimes.contains_key(path)
+    }
+
+    /// Returns all watched paths whose modification time changed since the last call
+    /// (or since they were first watched), including ones that were created or deleted
+    pub fn poll(&mut self) -> BTreeSet<PathBuf> {
+        let mut res = BTreeSet::new();
+        for (path, mtime) in &mut self.mtimes {
+            let new = modified(path);
+            if new != *mtime {
+                *mtime = new;
+                res.insert(path.clone());
+            }
+        }
+        res
+    }
+}
+
+/// Keeps an output directory up to date with a directory tree of templates
+///
+/// Each call to [`update`](#method.update) only renders the templates that are new
+/// or whose source, included files or data files changed, and only copies the
+/// other files that are new or changed (see [`Engine::process_tree`](struct.Engine.html#method.process_tree)).
+/// Outputs of files that were removed from the input directory are kept
+#[derive(Debug, Clone)]
+pub struct TreeWatcher {
+    input: PathBuf,
+    output: PathBuf,
+    options: TreeOptions,
+    /// The files that every rendered file depends on, by its path relative to `input`
+    deps: HashMap<PathBuf, BTreeSet<PathBuf>>,
+    files: FileWatcher,
+}
+
+impl TreeWatcher {
+    /// Creates a watcher that has not rendered anything yet
+    #[inline]
+    pub fn new(input: PathBuf, output: PathBuf, options: TreeOptions) -> Self {
+        Self {
+            input,
+            output,
+            options,
+            deps: HashMap::new(),
+            files: FileWatcher::new(),
+        }
+    }
+
+    /// Makes the next [`update`](#method.update) render everything again,
+    /// e.g. because the variables of the engine changed
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.deps.clear();
+    }
+
+    /// Renders everything that changed since the last update (everything on the first one)
+    ///
+    /// The report only contains the files that were rendered or copied in this update
+    ///
+    /// # Fails
+    /// If the input directory can't be walked
+    pub fn update(&mut self, engine: &Engine<Free>) -> std::io::Result<TreeReport> {
+        let paths = tree_paths(&self.input, &self.output)?;
+        let changed = self.files.poll();
+
+        let present = paths.iter().collect::<HashSet<_>>();
+        self.deps.retain(|p, _| present.contains(p));
+        let outdated = paths
+            .into_iter()
+            .filter(|p| match self.deps.get(p) {
+                Some(deps) => !deps.is_disjoint(&changed),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        // watched before rendering, so that changes made meanwhile are not missed
+        for path in &outdated {
+            self.files.watch(self.input.join(path));
+        }
+        let start = SystemTime::now();
+        let files = engine.render_tree_paths(&self.input, &self.output, &self.options, outdated);
+        for file in &files {
+            for path in &file.dependencies {
+                self.files.watch_since(path.clone(), start);
+            }
+            let mut deps = file.dependencies.clone();
+            deps.insert(self.input.join(&file.input));
+            self.deps.insert(file.input.clone(), deps);
+        }
+        // paths that no file depends on anymore don't need to be polled
+        let needed = self
+            .deps
+            .values()
+            .flatten()
+            .map(PathBuf::as_path)
+            .collect::<HashSet<_>>();
+        self.files.retain(|p| needed.contains(p));
+        Ok(TreeReport { files })
+    }
+}